@@ -1,13 +1,16 @@
-use cachedhash::CachedHash;
+use cachedhash::{
+    BuildPassThroughHasher, CachedHash, LocalCachedHash, NoSentinel, RemixZero, SentinelPolicy,
+};
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::collections::hash_map::{DefaultHasher, RandomState};
 use std::fmt::Formatter;
-use std::hash::Hash;
+use std::hash::{BuildHasher, BuildHasherDefault, Hash};
 use std::{collections::HashMap, fmt::Display};
 
 #[inline]
-fn move_things_around<T: Hash + Eq>(
-    map1: &mut HashMap<T, ()>,
-    map2: &mut HashMap<T, ()>,
+fn move_things_around<T: Hash + Eq, S: BuildHasher>(
+    map1: &mut HashMap<T, (), S>,
+    map2: &mut HashMap<T, (), S>,
     steps: usize,
 ) {
     for _ in 0..steps {
@@ -35,21 +38,82 @@ fn bench(c: &mut Criterion) {
             for &steps in [1, 5, 10, 20].iter() {
                 let mut data = vec![];
                 for j in 0..map_size {
-                    data.push(j.to_string().repeat(word_length as usize));
+                    data.push(j.to_string().repeat(word_length));
                 }
-                bench_hashmap("Regular", &mut group, map_size, word_length, steps, &data);
-                let data = data
-                    .into_iter()
-                    .map(|s| CachedHash::new(s))
+                bench_hashmap::<_, RandomState>(
+                    "Regular",
+                    &mut group,
+                    map_size,
+                    word_length,
+                    steps,
+                    &data,
+                );
+                let local_data = data
+                    .iter()
+                    .cloned()
+                    .map(LocalCachedHash::new)
                     .collect::<Vec<_>>();
-                bench_hashmap("Cached", &mut group, map_size, word_length, steps, &data);
+                let remix_data = with_sentinel(&data, RemixZero);
+                let no_sentinel_data = with_sentinel(&data, NoSentinel);
+                let data = data.into_iter().map(CachedHash::new).collect::<Vec<_>>();
+                bench_hashmap::<_, RandomState>(
+                    "Cached",
+                    &mut group,
+                    map_size,
+                    word_length,
+                    steps,
+                    &data,
+                );
+                bench_hashmap::<_, BuildPassThroughHasher>(
+                    "CachedPassThrough",
+                    &mut group,
+                    map_size,
+                    word_length,
+                    steps,
+                    &data,
+                );
+                bench_hashmap::<_, BuildPassThroughHasher>(
+                    "CachedPassThroughRemixZero",
+                    &mut group,
+                    map_size,
+                    word_length,
+                    steps,
+                    &remix_data,
+                );
+                bench_hashmap::<_, BuildPassThroughHasher>(
+                    "CachedPassThroughNoSentinel",
+                    &mut group,
+                    map_size,
+                    word_length,
+                    steps,
+                    &no_sentinel_data,
+                );
+                bench_hashmap::<_, RandomState>(
+                    "LocalCached",
+                    &mut group,
+                    map_size,
+                    word_length,
+                    steps,
+                    &local_data,
+                );
             }
         }
     }
     group.finish();
 }
 
-fn bench_hashmap<T: Eq + Hash + Clone>(
+fn with_sentinel<P: SentinelPolicy>(
+    data: &[String],
+    sentinel: P,
+) -> Vec<CachedHash<String, BuildHasherDefault<DefaultHasher>, P>> {
+    data.iter()
+        .map(|value| {
+            CachedHash::new_with_sentinel(value.clone(), BuildHasherDefault::default(), sentinel)
+        })
+        .collect()
+}
+
+fn bench_hashmap<T: Eq + Hash + Clone, S: BuildHasher + Default>(
     name: &str,
     group: &mut criterion::BenchmarkGroup<criterion::measurement::WallTime>,
     map_size: usize,
@@ -62,11 +126,11 @@ fn bench_hashmap<T: Eq + Hash + Clone>(
         data,
         |b, data| {
             b.iter(|| {
-                let mut map = HashMap::new();
+                let mut map = HashMap::<T, (), S>::default();
                 for key in data {
                     map.insert(key.clone(), ());
                 }
-                move_things_around(&mut map, &mut HashMap::new(), steps);
+                move_things_around(&mut map, &mut HashMap::default(), steps);
             })
         },
     );
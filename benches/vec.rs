@@ -0,0 +1,48 @@
+use cachedhash::{CachedHash, CachedHashVec};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use oorandom::Rand64;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{BuildHasher, BuildHasherDefault};
+
+const EDITS: usize = 100;
+
+fn random_edits(len: usize, seed: u128) -> Vec<(usize, u64)> {
+    let mut rng = Rand64::new(seed);
+    (0..EDITS)
+        .map(|_| (rng.rand_range(0..len as u64) as usize, rng.rand_u64()))
+        .collect()
+}
+
+fn bench(c: &mut Criterion) {
+    let mut group = c.benchmark_group("Vec");
+    let build_hasher = BuildHasherDefault::<DefaultHasher>::default();
+    for &len in [1_000, 100_000, 1_000_000].iter() {
+        let edits = random_edits(len, len as u128);
+        group.bench_with_input(BenchmarkId::new("CachedHash", len), &edits, |b, edits| {
+            let mut vec = CachedHash::new(vec![0_u64; len]);
+            b.iter(|| {
+                for &(index, value) in edits {
+                    CachedHash::get_mut(&mut vec)[index] = value;
+                    build_hasher.hash_one(&vec);
+                }
+            })
+        });
+        group.bench_with_input(
+            BenchmarkId::new("CachedHashVec", len),
+            &edits,
+            |b, edits| {
+                let mut vec = CachedHashVec::new(vec![0_u64; len]);
+                b.iter(|| {
+                    for &(index, value) in edits {
+                        *CachedHashVec::get_mut(&mut vec, index).unwrap() = value;
+                        build_hasher.hash_one(&vec);
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
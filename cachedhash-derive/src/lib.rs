@@ -0,0 +1,450 @@
+#![forbid(unsafe_code)]
+#![warn(clippy::pedantic)]
+#![warn(clippy::cargo)]
+#![warn(clippy::nursery)]
+
+//! Derive macro for the [`cachedhash`](https://docs.rs/cachedhash) crate.
+//!
+//! Use it through the `derive` feature of `cachedhash` which re-exports it
+//! as `cachedhash::CachedHash`. See `cachedhash::HashCache` for an example.
+
+use proc_macro::TokenStream;
+use proc_macro2::{TokenStream as TokenStream2, TokenTree};
+use quote::{format_ident, quote, ToTokens};
+use syn::ext::IdentExt;
+use syn::{
+    parse_macro_input, parse_quote, Data, DeriveInput, Error, Field, Fields, Ident, Meta, Type,
+};
+
+/// Implements [`Hash`](core::hash::Hash) for a struct with a `HashCache` field
+/// which caches the hash, and generates accessors for the other fields.
+///
+/// Every field is hashed unless marked with `#[hash(skip)]`, `#[hash]` can be
+/// used to mark the hashed fields explicitly. The cache is the single field
+/// whose type is named `HashCache`.
+///
+/// For each field `foo` of type `T` other than the cache the struct gets the
+/// methods `foo(&self) -> &T`, `set_foo(&mut self, T)` and
+/// `foo_mut(&mut self) -> &mut T` with the visibility of the field. The latter
+/// two invalidate the cached hash if `foo` is hashed. Modifying a hashed field
+/// directly does not invalidate the hash so keep hashed fields private.
+///
+/// `#[hash(no_getter)]` (or `#[hash(skip, no_getter)]`) leaves out the getter
+/// `foo`, for example when the struct defines its own method of that name.
+#[proc_macro_derive(CachedHash, attributes(hash))]
+pub fn derive_cached_hash(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// What a field of the deriving struct is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Hashed,
+    Skipped,
+    Cache,
+}
+
+/// How a field of the deriving struct is handled, given by its type and
+/// `#[hash]` attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FieldOptions {
+    role: Role,
+    getter: bool,
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Struct(data) = &input.data else {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "`CachedHash` can only be derived for structs",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "`CachedHash` can only be derived for structs with named fields",
+        ));
+    };
+    let fields = fields
+        .named
+        .iter()
+        .map(|field| Ok((field, options(field)?)))
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    let mut caches = fields
+        .iter()
+        .filter(|(_, options)| options.role == Role::Cache);
+    let Some((cache, _)) = caches.next() else {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "`CachedHash` needs a field of type `HashCache`",
+        ));
+    };
+    if let Some((other, _)) = caches.next() {
+        return Err(Error::new_spanned(
+            other,
+            "`CachedHash` needs exactly one field of type `HashCache`",
+        ));
+    }
+    let cache = cache.ident.as_ref().expect("fields are named");
+
+    let hash_impl = hash_impl(input, cache, &fields);
+    let accessors_impl = accessors_impl(input, cache, &fields);
+    Ok(quote! {
+        #hash_impl
+        #accessors_impl
+    })
+}
+
+/// Generates the [`Hash`](core::hash::Hash) implementation which hashes the
+/// fields through the cache.
+fn hash_impl(
+    input: &DeriveInput,
+    cache: &Ident,
+    fields: &[(&Field, FieldOptions)],
+) -> TokenStream2 {
+    let name = &input.ident;
+    let hashed = fields
+        .iter()
+        .filter(|(_, options)| options.role == Role::Hashed)
+        .map(|(field, _)| *field)
+        .collect::<Vec<_>>();
+    let hashed_idents = hashed.iter().map(|field| &field.ident);
+
+    // Like `#[derive(Hash)]` the impl requires the type parameters to be
+    // hashable, but only those used by hashed fields.
+    let mut generics = input.generics.clone();
+    let params = input
+        .generics
+        .type_params()
+        .map(|param| &param.ident)
+        .collect::<Vec<_>>();
+    let bounded = hashed
+        .iter()
+        .map(|field| &field.ty)
+        .filter(|ty| mentions_any(ty.to_token_stream(), &params))
+        .collect::<Vec<_>>();
+    if !bounded.is_empty() {
+        let where_clause = generics.make_where_clause();
+        for ty in bounded {
+            where_clause
+                .predicates
+                .push(parse_quote!(#ty: ::core::hash::Hash));
+        }
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    quote! {
+        #[automatically_derived]
+        impl #impl_generics ::core::hash::Hash for #name #ty_generics #where_clause {
+            fn hash<__H: ::core::hash::Hasher>(&self, state: &mut __H) {
+                ::cachedhash::HashCache::hash_fields::<Self, _, _>(
+                    &self.#cache,
+                    &(#(&self.#hashed_idents,)*),
+                    state,
+                );
+            }
+        }
+    }
+}
+
+/// Generates the getters, setters and mutable getters of the fields other
+/// than the cache.
+fn accessors_impl(
+    input: &DeriveInput,
+    cache: &Ident,
+    fields: &[(&Field, FieldOptions)],
+) -> TokenStream2 {
+    let name = &input.ident;
+    let accessors = fields
+        .iter()
+        .filter(|(_, options)| options.role != Role::Cache)
+        .map(|(field, options)| {
+            let ident = field.ident.as_ref().expect("fields are named");
+            let vis = &field.vis;
+            let ty = &field.ty;
+            let plain = ident.unraw();
+            let setter = format_ident!("set_{}", plain);
+            let getter_mut = format_ident!("{}_mut", plain);
+            let (invalidate, effect) = if options.role == Role::Hashed {
+                (
+                    Some(quote! {
+                        ::cachedhash::HashCache::invalidate_for_mutation(&mut self.#cache);
+                    }),
+                    "invalidates the cached hash",
+                )
+            } else {
+                (None, "keeps the cached hash as it is not hashed")
+            };
+            let get_doc = format!("Returns a reference to `{plain}`.");
+            let set_doc = format!("Sets `{plain}`, this {effect}.");
+            let mut_doc = format!("Returns a mutable reference to `{plain}`, this {effect}.");
+            let getter = options.getter.then(|| {
+                quote! {
+                    #[doc = #get_doc]
+                    #[inline]
+                    #vis fn #ident(&self) -> &#ty {
+                        &self.#ident
+                    }
+                }
+            });
+            quote! {
+                #getter
+
+                #[doc = #set_doc]
+                #[inline]
+                #vis fn #setter(&mut self, value: #ty) {
+                    #invalidate
+                    self.#ident = value;
+                }
+
+                #[doc = #mut_doc]
+                #[inline]
+                #vis fn #getter_mut(&mut self) -> &mut #ty {
+                    #invalidate
+                    &mut self.#ident
+                }
+            }
+        });
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    quote! {
+        #[allow(dead_code)]
+        impl #impl_generics #name #ty_generics #where_clause {
+            #(#accessors)*
+        }
+    }
+}
+
+/// Returns the options of a field given by its type and `#[hash]` attribute.
+fn options(field: &Field) -> syn::Result<FieldOptions> {
+    let mut options = None;
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("hash"))
+    {
+        if options.is_some() {
+            return Err(Error::new_spanned(attr, "duplicate `hash` attribute"));
+        }
+        options = Some(match &attr.meta {
+            Meta::Path(_) => FieldOptions {
+                role: Role::Hashed,
+                getter: true,
+            },
+            Meta::List(_) => {
+                let (mut skip, mut no_getter, mut any) = (false, false, false);
+                attr.parse_nested_meta(|meta| {
+                    any = true;
+                    if meta.path.is_ident("skip") {
+                        skip = true;
+                        Ok(())
+                    } else if meta.path.is_ident("no_getter") {
+                        no_getter = true;
+                        Ok(())
+                    } else {
+                        Err(meta.error("expected `skip` or `no_getter`"))
+                    }
+                })?;
+                if !any {
+                    return Err(Error::new_spanned(
+                        attr,
+                        "expected `#[hash(skip)]` or `#[hash(no_getter)]`",
+                    ));
+                }
+                FieldOptions {
+                    role: if skip { Role::Skipped } else { Role::Hashed },
+                    getter: !no_getter,
+                }
+            }
+            Meta::NameValue(_) => {
+                return Err(Error::new_spanned(
+                    attr,
+                    "expected `#[hash]`, `#[hash(skip)]` or `#[hash(no_getter)]`",
+                ))
+            }
+        });
+    }
+    if is_hash_cache(&field.ty) {
+        if let Some(attr) = field.attrs.iter().find(|attr| attr.path().is_ident("hash")) {
+            return Err(Error::new_spanned(
+                attr,
+                "the `HashCache` field cannot have a `hash` attribute",
+            ));
+        }
+        return Ok(FieldOptions {
+            role: Role::Cache,
+            getter: false,
+        });
+    }
+    Ok(options.unwrap_or(FieldOptions {
+        role: Role::Hashed,
+        getter: true,
+    }))
+}
+
+/// Returns `true` if `ty` is a path whose last segment is `HashCache`.
+fn is_hash_cache(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => {
+            path.qself.is_none()
+                && path
+                    .path
+                    .segments
+                    .last()
+                    .is_some_and(|segment| segment.ident == "HashCache")
+        }
+        _ => false,
+    }
+}
+
+/// Returns `true` if `tokens` contain any of the identifiers in `idents`.
+fn mentions_any(tokens: TokenStream2, idents: &[&Ident]) -> bool {
+    tokens.into_iter().any(|token| match token {
+        TokenTree::Ident(ident) => idents.contains(&&ident),
+        TokenTree::Group(group) => mentions_any(group.stream(), idents),
+        TokenTree::Punct(_) | TokenTree::Literal(_) => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[allow(clippy::needless_pass_by_value)] // called with `parse_quote!`
+    fn expand_err(input: DeriveInput) -> String {
+        expand(&input).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn roles() {
+        let input: DeriveInput = parse_quote! {
+            struct Foo {
+                #[hash]
+                a: u8,
+                b: u8,
+                #[hash(skip)]
+                c: u8,
+                #[hash(no_getter)]
+                d: u8,
+                #[hash(skip, no_getter)]
+                e: u8,
+                f: ::cachedhash::HashCache,
+            }
+        };
+        let Data::Struct(data) = &input.data else {
+            unreachable!()
+        };
+        let options = data
+            .fields
+            .iter()
+            .map(options)
+            .collect::<syn::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            options
+                .iter()
+                .map(|options| (options.role, options.getter))
+                .collect::<Vec<_>>(),
+            [
+                (Role::Hashed, true),
+                (Role::Hashed, true),
+                (Role::Skipped, true),
+                (Role::Hashed, false),
+                (Role::Skipped, false),
+                (Role::Cache, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn accessors_have_field_visibility() {
+        let input: DeriveInput = parse_quote! {
+            pub struct Foo {
+                pub a: u8,
+                pub(crate) b: u8,
+                c: u8,
+                #[hash(no_getter)]
+                pub d: u8,
+                cache: HashCache,
+            }
+        };
+        let expanded = expand(&input).unwrap().to_string();
+        assert!(expanded.contains("pub fn a (& self)"));
+        assert!(expanded.contains("pub fn set_a"));
+        assert!(expanded.contains("pub (crate) fn b (& self)"));
+        assert!(expanded.contains("pub (crate) fn b_mut"));
+        assert!(expanded.contains("] fn c (& self)"));
+        assert!(expanded.contains("] fn set_c"));
+        assert!(expanded.contains("] fn c_mut"));
+        assert!(!expanded.contains("fn d (& self)"));
+        assert!(expanded.contains("pub fn set_d"));
+        assert!(expanded.contains("pub fn d_mut"));
+    }
+
+    #[test]
+    fn bounds_only_hashed_type_parameters() {
+        let input: DeriveInput = parse_quote! {
+            struct Foo<A, B, BH: BuildHasher> {
+                a: Vec<A>,
+                #[hash(skip)]
+                b: B,
+                cache: HashCache<BH>,
+            }
+        };
+        let expanded = expand(&input).unwrap().to_string();
+        assert!(expanded.contains("where Vec < A > : :: core :: hash :: Hash"));
+        assert!(!expanded.contains("B : :: core :: hash :: Hash"));
+    }
+
+    #[test]
+    fn raw_identifiers() {
+        let input: DeriveInput = parse_quote! {
+            struct Foo {
+                r#type: u8,
+                cache: HashCache,
+            }
+        };
+        let expanded = expand(&input).unwrap().to_string();
+        assert!(expanded.contains("fn r#type"));
+        assert!(expanded.contains("fn set_type"));
+        assert!(expanded.contains("fn type_mut"));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            expand_err(parse_quote! { struct Foo { a: u8 } }),
+            "`CachedHash` needs a field of type `HashCache`"
+        );
+        assert_eq!(
+            expand_err(parse_quote! { struct Foo { a: HashCache, b: HashCache } }),
+            "`CachedHash` needs exactly one field of type `HashCache`"
+        );
+        assert_eq!(
+            expand_err(parse_quote! { struct Foo(u8, HashCache); }),
+            "`CachedHash` can only be derived for structs with named fields"
+        );
+        assert_eq!(
+            expand_err(parse_quote! { enum Foo { A } }),
+            "`CachedHash` can only be derived for structs"
+        );
+        assert_eq!(
+            expand_err(parse_quote! { struct Foo { #[hash(other)] a: u8, c: HashCache } }),
+            "expected `skip` or `no_getter`"
+        );
+        assert_eq!(
+            expand_err(parse_quote! { struct Foo { #[hash()] a: u8, c: HashCache } }),
+            "expected `#[hash(skip)]` or `#[hash(no_getter)]`"
+        );
+        assert_eq!(
+            expand_err(parse_quote! { struct Foo { #[hash] #[hash] a: u8, c: HashCache } }),
+            "duplicate `hash` attribute"
+        );
+        assert_eq!(
+            expand_err(parse_quote! { struct Foo { a: u8, #[hash] c: HashCache } }),
+            "the `HashCache` field cannot have a `hash` attribute"
+        );
+    }
+}
@@ -1,4 +1,16 @@
-use std::{fmt::Debug, num::NonZeroU64, sync::atomic::AtomicU64};
+use core::fmt::Debug;
+use core::num::{NonZeroU32, NonZeroU64};
+use core::sync::atomic::Ordering;
+
+#[cfg(not(feature = "portable-atomic"))]
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64};
+#[cfg(feature = "portable-atomic")]
+use portable_atomic::{AtomicBool, AtomicU32, AtomicU64};
+
+#[cfg(all(not(target_has_atomic = "64"), not(feature = "portable-atomic")))]
+compile_error!(
+    "cachedhash requires 64-bit atomics, enable the `portable-atomic` feature on this target"
+);
 
 /// Think of this as a `Option<NonZeroU64>` but atomic.
 #[repr(transparent)]
@@ -10,14 +22,13 @@ impl AtomicOptionNonZeroU64 {
         Self(AtomicU64::new(0))
     }
 
-    #[allow(dead_code)]
-    pub fn new_some(value: NonZeroU64) -> Self {
-        Self(AtomicU64::new(value.into()))
+    pub const fn new_some(value: NonZeroU64) -> Self {
+        Self(AtomicU64::new(value.get()))
     }
 
     #[inline]
     pub fn get(&self) -> Option<NonZeroU64> {
-        let value = self.0.load(std::sync::atomic::Ordering::Relaxed);
+        let value = self.0.load(Ordering::Relaxed);
         if value == 0 {
             None
         } else {
@@ -26,43 +37,136 @@ impl AtomicOptionNonZeroU64 {
     }
 
     #[inline]
-    pub fn get_raw(&self) -> Option<u64> {
-        let value = self.0.load(std::sync::atomic::Ordering::Relaxed);
-        if value == 0 {
-            None
+    pub fn set(&self, value: Option<NonZeroU64>) {
+        let value = value.map_or(0, Into::into);
+        self.0.store(value, Ordering::Relaxed);
+    }
+}
+
+impl Default for AtomicOptionNonZeroU64 {
+    fn default() -> Self {
+        Self::new_none()
+    }
+}
+
+impl Debug for AtomicOptionNonZeroU64 {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.get().fmt(f)
+    }
+}
+
+impl Clone for AtomicOptionNonZeroU64 {
+    fn clone(&self) -> Self {
+        Self(self.0.load(Ordering::Relaxed).into())
+    }
+}
+
+/// Think of this as a `Option<u64>` but atomic.
+///
+/// The hash is only ever set to a single value between invalidations (which
+/// require exclusive access) so it is enough to publish it with the flag
+/// using release and acquire ordering.
+#[allow(clippy::module_name_repetitions)]
+pub struct AtomicOptionU64 {
+    hash: AtomicU64,
+    present: AtomicBool,
+}
+
+impl AtomicOptionU64 {
+    pub const fn new_none() -> Self {
+        Self {
+            hash: AtomicU64::new(0),
+            present: AtomicBool::new(false),
+        }
+    }
+
+    pub const fn new_some(value: u64) -> Self {
+        Self {
+            hash: AtomicU64::new(value),
+            present: AtomicBool::new(true),
+        }
+    }
+
+    #[inline]
+    pub fn get(&self) -> Option<u64> {
+        if self.present.load(Ordering::Acquire) {
+            Some(self.hash.load(Ordering::Relaxed))
         } else {
-            Some(value)
+            None
         }
     }
 
     #[inline]
-    pub fn set(&self, value: Option<NonZeroU64>) {
+    pub fn set(&self, value: Option<u64>) {
+        match value {
+            Some(value) => {
+                self.hash.store(value, Ordering::Relaxed);
+                self.present.store(true, Ordering::Release);
+            }
+            None => self.present.store(false, Ordering::Relaxed),
+        }
+    }
+}
+
+impl Debug for AtomicOptionU64 {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.get().fmt(f)
+    }
+}
+
+impl Clone for AtomicOptionU64 {
+    fn clone(&self) -> Self {
+        self.get().map_or_else(Self::new_none, Self::new_some)
+    }
+}
+
+/// Think of this as a `Option<NonZeroU32>` but atomic.
+#[repr(transparent)]
+#[allow(clippy::module_name_repetitions)]
+pub struct AtomicOptionNonZeroU32(AtomicU32);
+
+impl AtomicOptionNonZeroU32 {
+    pub const fn new_none() -> Self {
+        Self(AtomicU32::new(0))
+    }
+
+    pub const fn new_some(value: NonZeroU32) -> Self {
+        Self(AtomicU32::new(value.get()))
+    }
+
+    #[inline]
+    pub fn get(&self) -> Option<NonZeroU32> {
+        NonZeroU32::new(self.0.load(Ordering::Relaxed))
+    }
+
+    #[inline]
+    pub fn set(&self, value: Option<NonZeroU32>) {
         let value = value.map_or(0, Into::into);
-        self.0.store(value, std::sync::atomic::Ordering::Relaxed);
+        self.0.store(value, Ordering::Relaxed);
     }
 }
 
-impl Default for AtomicOptionNonZeroU64 {
+impl Default for AtomicOptionNonZeroU32 {
     fn default() -> Self {
         Self::new_none()
     }
 }
 
-impl Debug for AtomicOptionNonZeroU64 {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl Debug for AtomicOptionNonZeroU32 {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         self.get().fmt(f)
     }
 }
 
-impl Clone for AtomicOptionNonZeroU64 {
+impl Clone for AtomicOptionNonZeroU32 {
     fn clone(&self) -> Self {
-        Self(self.0.load(std::sync::atomic::Ordering::Relaxed).into())
+        Self(self.0.load(Ordering::Relaxed).into())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::num::NonZeroU64;
+    use core::num::{NonZeroU32, NonZeroU64};
 
     use super::*;
 
@@ -70,15 +174,35 @@ mod tests {
     fn test_atomic_option_non_zero_u64() {
         let atomic = AtomicOptionNonZeroU64::new_none();
         assert_eq!(atomic.get(), None);
-        assert_eq!(atomic.get_raw(), None);
         atomic.set(Some(NonZeroU64::new(1).unwrap()));
         assert_eq!(atomic.get(), Some(NonZeroU64::new(1).unwrap()));
-        assert_eq!(atomic.get_raw(), Some(1));
         atomic.set(None);
         assert_eq!(atomic.get(), None);
-        assert_eq!(atomic.get_raw(), None);
         let atomic = AtomicOptionNonZeroU64::new_some(NonZeroU64::new(1).unwrap());
         assert_eq!(atomic.get(), Some(NonZeroU64::new(1).unwrap()));
-        assert_eq!(atomic.get_raw(), Some(1));
+    }
+
+    #[test]
+    fn test_atomic_option_u64() {
+        let atomic = AtomicOptionU64::new_none();
+        assert_eq!(atomic.get(), None);
+        atomic.set(Some(0));
+        assert_eq!(atomic.get(), Some(0));
+        atomic.set(None);
+        assert_eq!(atomic.get(), None);
+        let atomic = AtomicOptionU64::new_some(u64::MAX);
+        assert_eq!(atomic.get(), Some(u64::MAX));
+    }
+
+    #[test]
+    fn test_atomic_option_non_zero_u32() {
+        let atomic = AtomicOptionNonZeroU32::new_none();
+        assert_eq!(atomic.get(), None);
+        atomic.set(Some(NonZeroU32::new(1).unwrap()));
+        assert_eq!(atomic.get(), Some(NonZeroU32::new(1).unwrap()));
+        atomic.set(None);
+        assert_eq!(atomic.get(), None);
+        let atomic = AtomicOptionNonZeroU32::new_some(NonZeroU32::MAX);
+        assert_eq!(atomic.get(), Some(NonZeroU32::MAX));
     }
 }
@@ -1,21 +1,36 @@
-use std::borrow::{Borrow, BorrowMut};
-use std::cmp::Ordering;
+#[cfg(doc)]
+use core::borrow::Borrow;
+#[cfg(feature = "std")]
+use core::hash::BuildHasherDefault;
+#[cfg(doc)]
+use core::hash::Hasher;
+use core::hash::{BuildHasher, Hash};
+#[cfg(doc)]
+use core::ops::{Deref, DerefMut};
+#[cfg(feature = "std")]
 use std::collections::hash_map::DefaultHasher;
-use std::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
-use std::num::NonZeroU64;
-use std::ops::{Deref, DerefMut};
 
-use crate::atomic::AtomicOptionNonZeroU64;
+use crate::sentinel::{BumpZero, SentinelPolicy};
 
-/// For a type `T`, [`CachedHash`] wraps `T` and implements [`Hash`] in a way that
-/// caches `T`'s hash value. The first time the hash is computed, it is stored
+#[cfg(feature = "std")]
+mod boxed;
+pub mod wrapper;
+
+/// For a type `T`, [`CachedHash`](struct@CachedHash) wraps `T` and implements [`Hash`] in a way that
+/// caches `T`'s hash value.
+///
+/// The first time the hash is computed, it is stored
 /// and returned on subsequent calls. When the stored value is accessed mutably
-/// the hash is invalidated and needs to be recomputed again. [`CachedHash`] implements
+/// the hash is invalidated and needs to be recomputed again. [`CachedHash`](struct@CachedHash) implements
 /// [`Deref`] and [`DerefMut`] so it can be used as a drop-in replacement for `T`.
+/// The struct itself puts no bounds on `T`: [`Hash`] needs `T: Hash`, [`PartialEq`]
+/// needs `T: PartialEq` and so on.
 ///
 /// In order for the hash to be invalidated correctly the stored type cannot use
 /// interior mutability in a way that affects the hash. If this is the case, you
 /// can use [`CachedHash::invalidate_hash`] to invalidate the hash manually.
+/// To track down places where this goes wrong enable the `verify` feature
+/// which recomputes the hash whenever the cached one is used and panics if it is stale.
 ///
 /// By default the internal hash is computed using [`DefaultHasher`]. You can
 /// change this by providing a custom [`Hasher`] to [`CachedHash::new_with_hasher`] or
@@ -23,22 +38,26 @@ use crate::atomic::AtomicOptionNonZeroU64;
 /// cases you should not need to do this.
 ///
 /// Note that the hash of a value of type `T` and the same value wrapped in
-/// [`CachedHash`] are generally different.
+/// [`CachedHash`](struct@CachedHash) are generally different. This means that the [`Borrow<T>`]
+/// implementation does not uphold the requirement that borrowed and owned
+/// values hash the same way. Looking up `&T` in a `HashMap<CachedHash<T>, V>`
+/// will not find the entry. Use [`CachedHashMap`](crate::CachedHashMap) which
+/// supports lookups by `&T` instead.
 ///
 /// # Why is this useful?
 ///
 /// Sometimes you have a type that is expensive to hash (for example because)
 /// it is very large) but you need to store and move it between multiple
 /// [`HashSet`](https://doc.rust-lang.org/std/collections/struct.HashSet.html)s
-/// In this case you can wrap the type in [`CachedHash`] to cache the hash value
+/// In this case you can wrap the type in [`CachedHash`](struct@CachedHash) to cache the hash value
 /// only once.
 ///
-/// However, when the type is modified often [`CachedHash`] loses its advantage
-/// as the hash will get invalidated on every modification. [`CachedHash`] also
+/// However, when the type is modified often [`CachedHash`](struct@CachedHash) loses its advantage
+/// as the hash will get invalidated on every modification. [`CachedHash`](struct@CachedHash) also
 /// needs to store the [`u64`] hash value which takes up some space.
 ///
 /// You can run `cargo bench` to see some simple naive benchmarks comparing
-/// a plain `HashSet` with a `HashSet` that stores values wrapped in [`CachedHash`].
+/// a plain `HashSet` with a `HashSet` that stores values wrapped in [`CachedHash`](struct@CachedHash).
 ///
 /// # Details
 ///
@@ -53,348 +72,260 @@ use crate::atomic::AtomicOptionNonZeroU64;
 /// range of hash values and the possibility of the hash not being computed yet,
 /// we would need 65 bits. In order to save space we need to reserve one value
 /// as a sentinel (this also lets us work with the stored "maybe-hash" atomically).
-/// This means that we need to artificially create a hash collision. Current
-/// implementation does this by changing the "internal hash" from 0 to 1 if it ends
+/// This means that we need to artificially create a hash collision. By default
+/// this is done by changing the "internal hash" from 0 to 1 if it ends
 /// up being zero. This is generally not an issue. However, if you are using a custom hasher
-/// this might affect you.
+/// (such as an identity hasher) this might affect you.
+///
+/// The third type parameter, a [`SentinelPolicy`], chooses this behaviour. Use
+/// [`CachedHash::new_with_sentinel`] with [`RemixZero`](crate::RemixZero) to
+/// move the collision to an unlikely value or with
+/// [`NoSentinel`](crate::NoSentinel) to avoid it at the cost of 8 more bytes.
+///
+/// # Equality
+///
+/// When both compared values already have their internal hash cached and the
+/// hashes differ the values cannot be equal so [`PartialEq`] returns `false`
+/// without comparing the values themselves. This is only valid if both values
+/// compute their internal hash in the same way. As there is no way to compare
+/// two [`BuildHasher`]s this shortcut is only taken when `BH` is a zero-sized
+/// type (such as [`BuildHasherDefault`]) as then all its instances are
+/// indistinguishable. For a [`BuildHasher`] with state, such as
+/// [`RandomState`](std::collections::hash_map::RandomState), the values are
+/// always compared directly.
+///
+/// The shortcut goes wrong for a zero-sized [`BuildHasher`] whose instances
+/// do not all hash the same way, for example one whose hashers are seeded from
+/// a thread local. Equal values hashed on different threads would then compare
+/// unequal. Wrap such a [`BuildHasher`] in [`NoEqShortcut`] to opt out of the
+/// shortcut.
+///
+/// The cached hash is stored atomically so [`CachedHash`](struct@CachedHash) is [`Sync`] whenever
+/// `T` and `BH` are. If you never share the values between threads you can use
+/// [`LocalCachedHash`](crate::LocalCachedHash) instead which avoids the atomic
+/// operations.
+///
+/// `T` can be unsized, for example `Box<CachedHash<str>>` or
+/// `Arc<CachedHash<[u8]>>` avoid the double indirection of
+/// `CachedHash<Box<str>>`. Such values are created using the [`From`]
+/// implementations for [`Box`] or [`CachedHash::new_boxed`] and
+/// [`CachedHash::new_arc`].
 ///
-/// This behaviour is not guaranteed and may change in the future. If this
-/// behaviour does not fit for your use case please open an issue.
+/// Without the `std` feature there is no [`DefaultHasher`] so `BH` has no
+/// default and needs to be always specified.
+#[cfg(feature = "std")]
 #[derive(Debug)]
-pub struct CachedHash<T: Eq + Hash, BH: BuildHasher = BuildHasherDefault<DefaultHasher>> {
+#[repr(C)] // the layout is relied upon when allocating unsized values
+pub struct CachedHash<
+    T: ?Sized,
+    BH: BuildHasher = BuildHasherDefault<DefaultHasher>,
+    P: SentinelPolicy = BumpZero,
+> {
+    hash: P::AtomicSlot,
+    build_hasher: BH,
     value: T,
-    hash: AtomicOptionNonZeroU64,
+}
+
+/// For a type `T`, [`CachedHash`](struct@CachedHash) wraps `T` and implements [`Hash`] in a way that
+/// caches `T`'s hash value.
+///
+/// This is the `no_std` version of [`CachedHash`](struct@CachedHash) which requires the `BH`
+/// [`BuildHasher`] to be always specified. See the documentation of the crate
+/// built with the `std` feature for details.
+#[cfg(not(feature = "std"))]
+#[derive(Debug)]
+#[repr(C)]
+pub struct CachedHash<T: ?Sized, BH: BuildHasher, P: SentinelPolicy = BumpZero> {
+    hash: P::AtomicSlot,
     build_hasher: BH,
+    value: T,
 }
 
-impl<T: Eq + Hash + PartialOrd> PartialOrd for CachedHash<T> {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.value.partial_cmp(&other.value)
-    }
+wrapper::impl_cached_hash!(
+    CachedHash,
+    /// A guard giving mutable access to the value stored in a [`CachedHash`](struct@CachedHash).
+    ///
+    /// Created by [`CachedHash::modify`] and [`CachedHash::modify_with_policy`].
+    /// When dropped the cached hash is handled according to the [`RehashPolicy`]
+    /// it was created with. If the guard is dropped during a panic the hash is
+    /// left invalidated.
+    CachedHashGuard,
+    AtomicSlot,
+    BumpZero,
+);
+
+/// What happens to the cached hash when a [`CachedHashGuard`] (or
+/// [`LocalCachedHashGuard`](crate::LocalCachedHashGuard)) is dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum RehashPolicy {
+    /// The hash is recomputed and cached right away.
+    #[default]
+    Eager,
+    /// The hash is left invalidated and gets recomputed on the next
+    /// [`Hash::hash`] call, same as after [`CachedHash::get_mut`].
+    Lazy,
 }
 
-impl<T: Eq + Hash + Ord> Ord for CachedHash<T> {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.value.cmp(&other.value)
-    }
+/// Returns `true` if the current thread is unwinding because of a panic.
+///
+/// Without the `std` feature there is no way to tell so this always returns `false`.
+#[inline]
+#[allow(clippy::missing_const_for_fn)] // only const without `std`
+pub fn panicking() -> bool {
+    #[cfg(feature = "std")]
+    return std::thread::panicking();
+    #[cfg(not(feature = "std"))]
+    return false;
 }
 
-impl<T: Eq + Hash> CachedHash<T> {
-    /// Creates a new [`CachedHash`] with the given value using [`DefaultHasher`].
-    ///
-    /// Note that the [`BuildHasher`] stored in the structure is a zero-sized type
-    /// that is both [`Send`] and [`Sync`] so it will not affect the [`Send`] and [`Sync`]
-    /// properties of [`CachedHash`] nor its size.
-    pub fn new(value: T) -> Self {
-        Self::new_with_hasher(value)
-    }
+/// Computes the internal hash of `value` using a hasher built by `build_hasher`.
+///
+/// The hash is turned into the internal hash by the [`SentinelPolicy`] `P`
+/// which may create a small collision to keep a sentinel value free.
+#[inline]
+pub fn compute_internal_hash<T: Hash + ?Sized, BH: BuildHasher, P: SentinelPolicy>(
+    value: &T,
+    build_hasher: &BH,
+) -> P::Hash {
+    P::from_raw(build_hasher.hash_one(value))
 }
 
-impl<T: Eq + Hash, H: Hasher + Default> CachedHash<T, BuildHasherDefault<H>> {
-    /// Creates a new [`CachedHash`] with the given value using a provided hasher type implementing [`Default`].
-    ///
-    /// Note that the [`BuildHasher`] stored in the structure is a zero-sized type
-    /// that is both [`Send`] and [`Sync`] so it will not affect the [`Send`] and [`Sync`]
-    /// properties of [`CachedHash`] nor its size.
-    pub fn new_with_hasher(value: T) -> Self {
-        Self::new_with_build_hasher(value, BuildHasherDefault::default())
+/// Returns `true` if two cached maybe-hashes computed by `BH` prove that
+/// the values they were computed from are different.
+///
+/// This is only the case when both hashes are present and differ, and
+/// when `BH` is zero-sized so that both hashes were necessarily computed
+/// in the same way.
+#[inline]
+pub fn cached_hashes_differ<BH: BuildHasher, H: Eq>(
+    hash: Option<H>,
+    other_hash: Option<H>,
+) -> bool {
+    if core::mem::size_of::<BH>() != 0 {
+        return false;
+    }
+    match (hash, other_hash) {
+        (Some(hash), Some(other_hash)) => hash != other_hash,
+        _ => false,
     }
 }
 
-impl<T: Eq + Hash, BH: BuildHasher> CachedHash<T, BH> {
-    /// Creates a new [`CachedHash`] with the given value and [`BuildHasher`].
-    ///
-    /// Note that `build_hasher` is stored in the structure and as such it can
-    /// cause the type to stop being [`Send`] and [`Sync`] if the hasher is not.
-    /// It can also increase the size of the structure.
-    pub const fn new_with_build_hasher(value: T, build_hasher: BH) -> Self {
+/// A [`BuildHasher`] wrapper which opts out of the [`PartialEq`] shortcut
+/// taken when the cached internal hashes differ.
+///
+/// The shortcut is only taken for zero-sized [`BuildHasher`]s, assuming all
+/// their instances compute the same hashes. See the "Equality" section of
+/// [`CachedHash`](struct@CachedHash) for when that assumption fails.
+/// [`NoEqShortcut`] is never zero-sized so values using it are always compared
+/// directly. The price is the extra byte (and its padding) stored next to the
+/// wrapped [`BuildHasher`].
+///
+/// ```
+/// # #[cfg(feature = "std")] {
+/// use std::collections::hash_map::DefaultHasher;
+/// use std::hash::BuildHasherDefault;
+///
+/// use cachedhash::{CachedHash, NoEqShortcut};
+///
+/// type Build = NoEqShortcut<BuildHasherDefault<DefaultHasher>>;
+/// let foo = CachedHash::new_with_build_hasher("foo", Build::default());
+/// assert_eq!(foo, CachedHash::new_with_build_hasher("foo", Build::default()));
+/// # }
+/// ```
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoEqShortcut<BH> {
+    build_hasher: BH,
+    /// Makes the type non-zero-sized, see [`cached_hashes_differ`].
+    _not_zero_sized: u8,
+}
+
+impl<BH> NoEqShortcut<BH> {
+    /// Wraps `build_hasher`.
+    pub const fn new(build_hasher: BH) -> Self {
         Self {
-            value,
-            hash: AtomicOptionNonZeroU64::new_none(),
             build_hasher,
+            _not_zero_sized: 0,
         }
     }
 
-    /// Explicitly invalidates the cached hash. This should not be necessary
-    /// in most cases as the hash will be automatically invalidated when
-    /// the value is accessed mutably. However, if the value uses interior
-    /// mutability in a way that affects the hash, you will need to call
-    /// this function manually whenever the hash might have changed.
-    #[inline]
-    pub fn invalidate_hash(this: &mut Self) {
-        this.hash.set(None);
-    }
-
-    /// Destructs the [`CachedHash`] and returns the stored value.
-    #[inline]
+    /// Returns the wrapped [`BuildHasher`].
     #[must_use]
-    #[allow(clippy::missing_const_for_fn)] // false positive, `this` might get dropped
-    pub fn take_value(this: Self) -> T {
-        this.value
-    }
-
-    /// Explicitly returns an immutable reference to the stored value.
-    ///
-    /// Most of the time this will not be necessary as [`CachedHash`]
-    /// implements [`Deref`] so autoderef rules will automatically dereference
-    /// it to the stored value.
-    #[inline]
-    #[must_use]
-    pub const fn get(this: &Self) -> &T {
-        &this.value
-    }
-
-    /// Explicitly returns a mutable reference to the stored value and
-    /// invalidates the cached hash.
-    ///
-    /// Most of the time this will not be necessary as [`CachedHash`] implements
-    /// [`DerefMut`] so autoderef rules will automatically dereference it to the
-    /// stored value. (Such dereference still invalidates the stored hash.)
-    #[inline]
-    #[must_use]
-    pub fn get_mut(this: &mut Self) -> &mut T {
-        Self::invalidate_hash(this);
-        &mut this.value
-    }
-}
-
-impl<T: Eq + Hash, BH: BuildHasher> PartialEq for CachedHash<T, BH> {
-    fn eq(&self, other: &Self) -> bool {
-        self.value == other.value
+    pub const fn get(&self) -> &BH {
+        &self.build_hasher
     }
 }
 
-impl<T: Eq + Hash, BH: BuildHasher> Eq for CachedHash<T, BH> {}
-
-impl<T: Eq + Hash, BH: BuildHasher> Hash for CachedHash<T, BH> {
-    fn hash<H2: Hasher>(&self, state: &mut H2) {
-        if let Some(hash) = self.hash.get_raw() {
-            state.write_u64(hash);
-        } else {
-            let mut hasher = self.build_hasher.build_hasher();
-            self.value.hash(&mut hasher);
-            // AtomicOptionNonZeroU64 can only store non-zero values so we create a small collision by bumping up hash 0 to 1.
-            let hash = NonZeroU64::new(hasher.finish()).unwrap_or(NonZeroU64::new(1).unwrap());
-            self.hash.set(Some(hash));
-            state.write_u64(hash.into());
-        }
-    }
-}
+impl<BH: BuildHasher> BuildHasher for NoEqShortcut<BH> {
+    type Hasher = BH::Hasher;
 
-impl<T: Eq + Hash, BH: BuildHasher> AsMut<T> for CachedHash<T, BH> {
-    fn as_mut(&mut self) -> &mut T {
-        Self::get_mut(self)
-    }
-}
-
-impl<T: Eq + Hash, BH: BuildHasher> AsRef<T> for CachedHash<T, BH> {
-    fn as_ref(&self) -> &T {
-        Self::get(self)
+    #[inline]
+    fn build_hasher(&self) -> Self::Hasher {
+        self.build_hasher.build_hasher()
     }
 }
 
-impl<T: Eq + Hash, BH: BuildHasher> BorrowMut<T> for CachedHash<T, BH> {
-    fn borrow_mut(&mut self) -> &mut T {
-        Self::get_mut(self)
-    }
-}
+#[cfg(test)]
+wrapper::cached_hash_tests!(CachedHash, CachedHashGuard);
 
-impl<T: Eq + Hash, BH: BuildHasher> Borrow<T> for CachedHash<T, BH> {
-    fn borrow(&self) -> &T {
-        Self::get(self)
-    }
-}
+/// Tests that only use the parts of the crate available without the `std` feature.
+#[cfg(test)]
+mod no_std_tests {
+    use core::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
+    use core::num::NonZeroU64;
 
-impl<T: Eq + Hash, BH: BuildHasher> Deref for CachedHash<T, BH> {
-    type Target = T;
+    use crate::{BuildPassThroughHasher, CachedHash, LocalCachedHash, RehashPolicy};
 
-    #[inline]
-    fn deref(&self) -> &Self::Target {
-        Self::get(self)
-    }
-}
+    /// A simple FNV-1a hasher that does not need `std`.
+    struct Fnv(u64);
 
-impl<T: Eq + Hash, BH: BuildHasher> DerefMut for CachedHash<T, BH> {
-    #[inline]
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        Self::get_mut(self)
+    impl Default for Fnv {
+        fn default() -> Self {
+            Self(0xcbf2_9ce4_8422_2325)
+        }
     }
-}
 
-impl<T: Eq + Hash, H: Hasher + Default> From<T> for CachedHash<T, BuildHasherDefault<H>> {
-    fn from(value: T) -> Self {
-        Self::new_with_hasher(value)
-    }
-}
+    impl Hasher for Fnv {
+        fn write(&mut self, bytes: &[u8]) {
+            for &byte in bytes {
+                self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+            }
+        }
 
-impl<T: Eq + Hash + Clone, BH: BuildHasher + Clone> Clone for CachedHash<T, BH> {
-    fn clone(&self) -> Self {
-        Self {
-            value: self.value.clone(),
-            hash: self.hash.clone(),
-            build_hasher: self.build_hasher.clone(),
+        fn finish(&self) -> u64 {
+            self.0
         }
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use std::collections::hash_map::DefaultHasher;
-    use std::hash::{Hash, Hasher};
-    use std::sync::atomic::AtomicBool;
 
-    use super::*;
+    type FnvBuild = BuildHasherDefault<Fnv>;
 
     fn calculate_hash<T: Hash>(t: &T) -> u64 {
-        calculate_hash_with_hasher::<T, DefaultHasher>(t)
-    }
-
-    fn calculate_hash_with_hasher<T: Hash, H: Hasher + Default>(t: &T) -> u64 {
-        let mut s = H::default();
-        t.hash(&mut s);
-        s.finish()
-    }
-
-    #[test]
-    fn hash_same_after_noop_mut_borrow() {
-        let mut foo = super::CachedHash::new("foo".to_string());
-        let hash = calculate_hash(&foo);
-        let _ = CachedHash::get_mut(&mut foo);
-        assert_eq!(hash, calculate_hash(&foo));
+        FnvBuild::default().hash_one(t)
     }
 
     #[test]
-    fn hash_different_after_modification() {
-        let mut foo = super::CachedHash::new("foo".to_string());
+    fn cached_hash_with_explicit_hasher() {
+        let mut foo: CachedHash<[u8; 3], FnvBuild> = CachedHash::new_with_hasher(*b"foo");
         let hash = calculate_hash(&foo);
-        foo.push('a');
-        // The first three lines are there to make sure the test doesn't start failing
-        // if the standard library hashing changes to create a collision in the test case.
-        // This way it would only make this test useless.
-        assert!(
-            calculate_hash(&"foo".to_string()) == 0 || // unlikely case when 0 gets internally converted to 1
-            calculate_hash(&"fooa".to_string()) == 0 || // ditto
-            calculate_hash(&"foo".to_string()) == calculate_hash(&"fooa".to_string()) || // unlikely hash collision
-            hash != calculate_hash(&foo)
+        assert_eq!(
+            CachedHash::cached_hash(&foo),
+            NonZeroU64::new(calculate_hash(b"foo"))
         );
-    }
-
-    #[test]
-    fn hash_same_after_invalidation() {
-        let mut foo = super::CachedHash::new("foo".to_string());
-        let hash = calculate_hash(&foo);
-        CachedHash::invalidate_hash(&mut foo);
-        assert_eq!(hash, calculate_hash(&foo));
-    }
-
-    #[test]
-    fn hash_same_after_clone() {
-        let foo = super::CachedHash::new("foo".to_string());
-        let hash = calculate_hash(&foo);
-        let foo2 = foo.clone();
-        assert_eq!(hash, calculate_hash(&foo2));
-    }
-
-    #[test]
-    fn hash_same_consecutive() {
-        let foo = super::CachedHash::new("foo".to_string());
-        let hash = calculate_hash(&foo);
-        assert_eq!(hash, calculate_hash(&foo));
-    }
-
-    #[test]
-    fn invalide_invalidates() {
-        let mut foo = super::CachedHash::new("foo".to_string());
-        assert!(foo.hash.get().is_none());
-        calculate_hash(&foo);
-        assert!(foo.hash.get().is_some());
-        CachedHash::invalidate_hash(&mut foo);
-        assert!(foo.hash.get().is_none());
-        calculate_hash(&foo);
-        assert!(foo.hash.get().is_some());
-    }
-
-    #[test]
-    fn mut_deref_invalidates() {
-        let mut foo = super::CachedHash::new("foo".to_string());
-        assert!(foo.hash.get().is_none());
-        calculate_hash(&foo);
-        assert!(foo.hash.get().is_some());
-        foo.push('a');
-        assert!(foo.hash.get().is_none());
-        calculate_hash(&foo);
-        assert!(foo.hash.get().is_some());
-        let _ = foo.len();
-        assert!(foo.hash.get().is_some());
-    }
-
-    #[test]
-    fn hash_gets_cached() {
-        struct YouOnlyHashOnce {
-            hashed_once: AtomicBool,
-        }
-        impl Eq for YouOnlyHashOnce {}
-        impl PartialEq for YouOnlyHashOnce {
-            fn eq(&self, _other: &Self) -> bool {
-                true
-            }
-        }
-        impl Hash for YouOnlyHashOnce {
-            fn hash<H: Hasher>(&self, _state: &mut H) {
-                if self
-                    .hashed_once
-                    .swap(true, std::sync::atomic::Ordering::SeqCst)
-                {
-                    panic!("Hashing should only happen once");
-                }
-            }
-        }
-
-        let foo = super::CachedHash::new(YouOnlyHashOnce {
-            hashed_once: AtomicBool::new(false),
-        });
-        calculate_hash(&foo);
-        calculate_hash(&foo);
-        calculate_hash(&foo);
-    }
-
-    #[test]
-    fn take_value() {
-        let foo = super::CachedHash::new("foo".to_string());
-        assert_eq!(CachedHash::take_value(foo), "foo".to_string());
-    }
-
-    #[test]
-    fn struct_is_small() {
-        assert!(
-            std::mem::size_of::<super::CachedHash<String>>()
-                <= std::mem::size_of::<String>() + std::mem::size_of::<u64>()
+        CachedHash::update(&mut foo, |value| value[0] = b'g');
+        assert_ne!(calculate_hash(&foo), hash);
+        CachedHash::modify_with_policy(&mut foo, RehashPolicy::Lazy)[0] = b'f';
+        assert_eq!(CachedHash::cached_hash(&foo), None);
+        assert_eq!(calculate_hash(&foo), hash);
+        assert_eq!(
+            BuildPassThroughHasher::default().hash_one(&foo),
+            calculate_hash(b"foo")
         );
     }
 
     #[test]
-    fn zero_hash() {
-        use nohash_hasher::NoHashHasher;
-
-        struct FixedHash<const H: u64>();
-        impl<const H: u64> Eq for FixedHash<H> {}
-        impl<const H: u64> PartialEq for FixedHash<H> {
-            fn eq(&self, _other: &Self) -> bool {
-                true
-            }
-        }
-        impl<const H: u64> Hash for FixedHash<H> {
-            fn hash<HS: Hasher>(&self, state: &mut HS) {
-                state.write_u64(H);
-            }
-        }
-
-        assert!(
-            calculate_hash_with_hasher::<FixedHash<0>, NoHashHasher<u64>>(&FixedHash::<0>()) == 0
-        );
-        let foo: CachedHash<_, BuildHasherDefault<NoHashHasher<u64>>> =
-            super::CachedHash::new_with_hasher(FixedHash::<0>());
-        let _ = calculate_hash(&foo);
-        assert!(foo.hash.get().is_some());
+    fn local_cached_hash_with_explicit_hasher() {
+        let foo: LocalCachedHash<u64, FnvBuild> = LocalCachedHash::new_hashed_with_hasher(42);
+        let bar: CachedHash<u64, FnvBuild> = CachedHash::new_with_hasher(42);
+        assert_eq!(calculate_hash(&foo), calculate_hash(&bar));
+        assert_eq!(foo, LocalCachedHash::new_with_hasher(42));
     }
 }
@@ -0,0 +1,268 @@
+//! Construction of [`CachedHash`](struct@CachedHash)es with unsized values such as `str` and `[T]`.
+//!
+//! Safe Rust can only create a custom dynamically sized type by unsizing a
+//! sized one, which for `[T]` requires the length to be known at compile time
+//! and for `str` is not possible at all. This module is therefore the only
+//! place in the crate allowed to use `unsafe` code. It allocates the
+//! [`CachedHash`](struct@CachedHash), which is `#[repr(C)]` with the value as its last field,
+//! and moves the elements of a boxed slice into it.
+#![allow(unsafe_code)]
+
+use std::alloc::{self, Layout};
+use std::hash::{BuildHasher, BuildHasherDefault, Hasher};
+use std::ptr;
+use std::sync::Arc;
+
+use super::CachedHash;
+use crate::sentinel::{BumpZero, SentinelPolicy, Slot};
+
+impl<T, BH: BuildHasher> CachedHash<[T], BH> {
+    /// Creates a new boxed [`CachedHash`](struct@CachedHash) with the given slice and [`BuildHasher`].
+    ///
+    /// The elements are moved into a new allocation holding the cached hash
+    /// next to them.
+    #[must_use]
+    pub fn new_boxed(value: Box<[T]>, build_hasher: BH) -> Box<Self> {
+        new_boxed_slice(value, build_hasher, BumpZero)
+    }
+
+    /// Creates a new reference counted [`CachedHash`](struct@CachedHash) with the given slice
+    /// and [`BuildHasher`].
+    ///
+    /// This creates a boxed [`CachedHash`](struct@CachedHash) first, which
+    /// [`Arc::from`] then copies into the reference counted allocation, so the
+    /// elements are moved twice. With a default [`BuildHasher`] the same can
+    /// be written as `Arc::from(Box::from(value))`.
+    #[must_use]
+    pub fn new_arc(value: Box<[T]>, build_hasher: BH) -> Arc<Self> {
+        Arc::from(Self::new_boxed(value, build_hasher))
+    }
+}
+
+impl<BH: BuildHasher> CachedHash<str, BH> {
+    /// Creates a new boxed [`CachedHash`](struct@CachedHash) with the given string and [`BuildHasher`].
+    #[must_use]
+    pub fn new_boxed(value: Box<str>, build_hasher: BH) -> Box<Self> {
+        new_boxed_str(value, build_hasher, BumpZero)
+    }
+
+    /// Creates a new reference counted [`CachedHash`](struct@CachedHash) with the given string
+    /// and [`BuildHasher`].
+    ///
+    /// This creates a boxed [`CachedHash`](struct@CachedHash) first, which
+    /// [`Arc::from`] then copies into the reference counted allocation, so the
+    /// bytes are copied twice. With a default [`BuildHasher`] the same can be
+    /// written as `Arc::from(Box::from(value))`.
+    #[must_use]
+    pub fn new_arc(value: Box<str>, build_hasher: BH) -> Arc<Self> {
+        Arc::from(Self::new_boxed(value, build_hasher))
+    }
+}
+
+/// Moves the elements of `value` into a newly allocated [`CachedHash`](struct@CachedHash).
+fn new_boxed_slice<T, BH: BuildHasher, P: SentinelPolicy>(
+    value: Box<[T]>,
+    build_hasher: BH,
+    _sentinel: P,
+) -> Box<CachedHash<[T], BH, P>> {
+    let len = value.len();
+    // The fields in declaration order as laid out by `#[repr(C)]`.
+    let (layout, _) = Layout::new::<P::AtomicSlot>()
+        .extend(Layout::new::<BH>())
+        .and_then(|(header, _)| header.extend(Layout::array::<T>(len)?))
+        .expect("slice too large");
+    let layout = layout.pad_to_align();
+    // `P::AtomicSlot` is never zero-sized so neither is `layout`.
+    debug_assert_ne!(layout.size(), 0);
+
+    // SAFETY: `layout` is not zero-sized.
+    let raw = unsafe { alloc::alloc(layout) };
+    if raw.is_null() {
+        alloc::handle_alloc_error(layout);
+    }
+    // The cast keeps the slice length as the pointer metadata.
+    let this = ptr::slice_from_raw_parts_mut(raw.cast::<T>(), len) as *mut CachedHash<[T], BH, P>;
+    let mut value = Vec::from(value);
+    // SAFETY: `this` points to an allocation with the size and alignment of
+    // `CachedHash<[T], BH, P>` with `len` elements, so all fields are in bounds
+    // and aligned. The elements are moved out of `value` whose length is then
+    // set to 0 so that they are not dropped twice. Afterwards every field is
+    // initialized and the allocation matches the layout `Box` deallocates with.
+    let this = unsafe {
+        ptr::addr_of_mut!((*this).hash).write(<P::AtomicSlot as Slot<P::Hash>>::NONE);
+        ptr::addr_of_mut!((*this).build_hasher).write(build_hasher);
+        ptr::copy_nonoverlapping(
+            value.as_ptr(),
+            ptr::addr_of_mut!((*this).value).cast::<T>(),
+            len,
+        );
+        value.set_len(0);
+        Box::from_raw(this)
+    };
+    debug_assert_eq!(Layout::for_value::<CachedHash<[T], BH, P>>(&this), layout);
+    this
+}
+
+/// Moves the bytes of `value` into a newly allocated [`CachedHash`](struct@CachedHash).
+fn new_boxed_str<BH: BuildHasher, P: SentinelPolicy>(
+    value: Box<str>,
+    build_hasher: BH,
+    sentinel: P,
+) -> Box<CachedHash<str, BH, P>> {
+    let bytes = new_boxed_slice(value.into_boxed_bytes(), build_hasher, sentinel);
+    // SAFETY: `CachedHash<[u8], BH, P>` and `CachedHash<str, BH, P>` have the
+    // same layout and pointer metadata, and the bytes came from a `str` so
+    // they are valid UTF-8.
+    unsafe { Box::from_raw(Box::into_raw(bytes) as *mut CachedHash<str, BH, P>) }
+}
+
+impl<H: Hasher + Default, P: SentinelPolicy> From<Box<str>>
+    for Box<CachedHash<str, BuildHasherDefault<H>, P>>
+{
+    fn from(value: Box<str>) -> Self {
+        new_boxed_str(value, BuildHasherDefault::default(), P::default())
+    }
+}
+
+impl<H: Hasher + Default, P: SentinelPolicy> From<String>
+    for Box<CachedHash<str, BuildHasherDefault<H>, P>>
+{
+    fn from(value: String) -> Self {
+        Self::from(value.into_boxed_str())
+    }
+}
+
+impl<H: Hasher + Default, P: SentinelPolicy> From<&str>
+    for Box<CachedHash<str, BuildHasherDefault<H>, P>>
+{
+    fn from(value: &str) -> Self {
+        Self::from(Box::<str>::from(value))
+    }
+}
+
+impl<T, H: Hasher + Default, P: SentinelPolicy> From<Box<[T]>>
+    for Box<CachedHash<[T], BuildHasherDefault<H>, P>>
+{
+    fn from(value: Box<[T]>) -> Self {
+        new_boxed_slice(value, BuildHasherDefault::default(), P::default())
+    }
+}
+
+impl<T, H: Hasher + Default, P: SentinelPolicy> From<Vec<T>>
+    for Box<CachedHash<[T], BuildHasherDefault<H>, P>>
+{
+    fn from(value: Vec<T>) -> Self {
+        Self::from(value.into_boxed_slice())
+    }
+}
+
+impl<T: Clone, H: Hasher + Default, P: SentinelPolicy> From<&[T]>
+    for Box<CachedHash<[T], BuildHasherDefault<H>, P>>
+{
+    fn from(value: &[T]) -> Self {
+        Self::from(Box::<[T]>::from(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::HashSet;
+    use std::hash::{Hash, Hasher};
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::{BuildPassThroughHasher, NoSentinel};
+
+    fn calculate_hash<T: Hash + ?Sized>(t: &T) -> u64 {
+        let mut s = DefaultHasher::default();
+        t.hash(&mut s);
+        s.finish()
+    }
+
+    #[test]
+    fn boxed_str() {
+        let mut foo: Box<CachedHash<str>> = "foo".into();
+        assert_eq!(&**foo, "foo");
+        assert_eq!(CachedHash::cached_hash(&foo), None);
+        let hash = calculate_hash(&foo);
+        assert_eq!(hash, calculate_hash(&CachedHash::new("foo")));
+        assert_eq!(hash, calculate_hash(&CachedHash::new("foo".to_string())));
+        foo.make_ascii_uppercase();
+        assert_eq!(CachedHash::cached_hash(&foo), None);
+        assert_eq!(&**foo, "FOO");
+        assert_ne!(calculate_hash(&foo), hash);
+    }
+
+    #[test]
+    fn boxed_slice() {
+        let mut foo: Box<CachedHash<[u64]>> = vec![1, 2, 3].into();
+        assert_eq!(**foo, [1, 2, 3]);
+        let hash = calculate_hash(&foo);
+        assert_eq!(hash, calculate_hash(&CachedHash::new(vec![1_u64, 2, 3])));
+        foo[0] = 4;
+        assert_ne!(calculate_hash(&foo), hash);
+        let bar: Box<CachedHash<[u64]>> = [4_u64, 2, 3][..].into();
+        assert_eq!(foo, bar);
+        assert!(foo > Box::from(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn drops_elements_once() {
+        let counter = Rc::new(());
+        let foo: Box<CachedHash<[Rc<()>]>> = vec![Rc::clone(&counter); 3].into();
+        assert_eq!(Rc::strong_count(&counter), 4);
+        drop(foo);
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    fn empty_and_zero_sized() {
+        let empty: Box<CachedHash<[u8]>> = Vec::new().into();
+        assert!(empty.is_empty());
+        let units: Box<CachedHash<[()]>> = vec![(); 5].into();
+        assert_eq!(units.len(), 5);
+        let empty: Box<CachedHash<str>> = "".into();
+        assert_eq!(&**empty, "");
+        let _ = calculate_hash(&empty);
+    }
+
+    #[test]
+    fn over_aligned_elements() {
+        #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+        #[repr(align(64))]
+        struct Aligned(u8);
+
+        let foo: Box<CachedHash<[Aligned]>> = vec![Aligned(1), Aligned(2)].into();
+        assert_eq!(foo.as_ptr().align_offset(64), 0);
+        assert_eq!(foo[1], Aligned(2));
+    }
+
+    #[test]
+    fn arc() {
+        let foo = CachedHash::<str>::new_arc("foo".into(), BuildHasherDefault::default());
+        let bar = Arc::clone(&foo);
+        let _ = CachedHash::internal_hash(&foo);
+        assert!(CachedHash::cached_hash(&bar).is_some());
+        let baz = CachedHash::<[u8]>::new_arc(b"foo"[..].into(), BuildHasherDefault::default());
+        assert_eq!(
+            CachedHash::internal_hash(&baz),
+            CachedHash::internal_hash(&CachedHash::new(b"foo".to_vec()))
+        );
+        let from_str: Arc<CachedHash<str>> = Arc::from(Box::from("foo"));
+        assert_eq!(from_str, foo);
+        let from_vec: Arc<CachedHash<[u8]>> = Arc::from(Box::from(b"foo".to_vec()));
+        assert_eq!(from_vec, baz);
+    }
+
+    #[test]
+    #[allow(clippy::mutable_key_type)]
+    fn with_sentinel_and_hash_set() {
+        let set: HashSet<
+            Box<CachedHash<str, BuildHasherDefault<DefaultHasher>, NoSentinel>>,
+            BuildPassThroughHasher,
+        > = ["foo", "bar", "foo"].into_iter().map(Box::from).collect();
+        assert_eq!(set.len(), 2);
+        assert!(set.contains(&Box::from("bar")));
+    }
+}
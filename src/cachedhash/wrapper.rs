@@ -0,0 +1,1232 @@
+//! The implementation shared by [`CachedHash`](struct@crate::CachedHash),
+//! [`LocalCachedHash`](crate::LocalCachedHash) and
+//! [`CachedHash32`](crate::CachedHash32).
+//!
+//! The wrappers have the fields `hash`, `build_hasher` and `value` and only
+//! differ in the [`Slot`](crate::sentinel::Slot) storing the cached hash:
+//! [`SentinelPolicy::AtomicSlot`](crate::SentinelPolicy) or
+//! [`SentinelPolicy::LocalSlot`](crate::SentinelPolicy), and in their default
+//! [`SentinelPolicy`](crate::SentinelPolicy), which also decides the width of
+//! the hash. [`impl_cached_hash`] generates their inherent functions, trait
+//! implementations and modification guard, and [`cached_hash_tests`] the tests
+//! shared by the 64-bit wrappers. The tests that only need the basic caching
+//! behaviour come from [`cached_hash_common_tests`] so that
+//! [`CachedHash128`](crate::CachedHash128) and
+//! [`CachedHash32`](crate::CachedHash32) can share them as well.
+
+/// Implements a cached hash wrapper.
+///
+/// Expects the wrapper `$name<T: ?Sized, BH, P = $default>` with the fields
+/// `hash: P::$slot`, `build_hasher: BH` and `value: T` to be already defined.
+/// Generates its modification guard `$guard` documented by the given attributes.
+/// The constructors that do not take a policy use `$default`.
+macro_rules! impl_cached_hash {
+    (
+        $name:ident,
+        $(#[$guard_meta:meta])*
+        $guard:ident,
+        $slot:ident,
+        $default:ident $(,)?
+    ) => {
+        use $crate::sentinel::Slot as _;
+
+        impl<T: PartialOrd + ?Sized, BH: ::core::hash::BuildHasher, P: $crate::SentinelPolicy>
+            PartialOrd for $name<T, BH, P>
+        {
+            fn partial_cmp(&self, other: &Self) -> Option<::core::cmp::Ordering> {
+                self.value.partial_cmp(&other.value)
+            }
+        }
+
+        impl<T: Ord + ?Sized, BH: ::core::hash::BuildHasher, P: $crate::SentinelPolicy> Ord
+            for $name<T, BH, P>
+        {
+            fn cmp(&self, other: &Self) -> ::core::cmp::Ordering {
+                self.value.cmp(&other.value)
+            }
+        }
+
+        #[cfg(feature = "std")]
+        impl<T> $name<T> {
+            /// Creates a new wrapper with the given value using
+            /// [`DefaultHasher`](std::collections::hash_map::DefaultHasher).
+            ///
+            /// Note that the [`BuildHasher`](core::hash::BuildHasher) stored in the
+            /// structure is a zero-sized type that is both [`Send`] and [`Sync`] so it
+            /// affects neither those properties of the wrapper nor its size.
+            pub fn new(value: T) -> Self {
+                Self::new_with_hasher(value)
+            }
+
+            /// Creates a new wrapper with the given value using
+            /// [`DefaultHasher`](std::collections::hash_map::DefaultHasher) and
+            /// computes its hash right away.
+            ///
+            /// See [`Self::new_hashed_with_build_hasher`] for details.
+            pub fn new_hashed(value: T) -> Self
+            where
+                T: ::core::hash::Hash,
+            {
+                Self::new_hashed_with_hasher(value)
+            }
+        }
+
+        impl<T, H: ::core::hash::Hasher + Default> $name<T, ::core::hash::BuildHasherDefault<H>> {
+            /// Creates a new wrapper with the given value using a provided hasher
+            /// type implementing [`Default`].
+            ///
+            /// Note that the [`BuildHasher`](core::hash::BuildHasher) stored in the
+            /// structure is a zero-sized type that is both [`Send`] and [`Sync`] so it
+            /// affects neither those properties of the wrapper nor its size.
+            pub fn new_with_hasher(value: T) -> Self {
+                Self::new_with_build_hasher(value, ::core::hash::BuildHasherDefault::default())
+            }
+
+            /// Creates a new wrapper with the given value using a provided hasher
+            /// type implementing [`Default`] and computes its hash right away.
+            ///
+            /// See [`Self::new_hashed_with_build_hasher`] for details.
+            pub fn new_hashed_with_hasher(value: T) -> Self
+            where
+                T: ::core::hash::Hash,
+            {
+                Self::new_hashed_with_build_hasher(
+                    value,
+                    ::core::hash::BuildHasherDefault::default(),
+                )
+            }
+        }
+
+        impl<T, BH: ::core::hash::BuildHasher> $name<T, BH> {
+            /// Creates a new wrapper with the given value and
+            /// [`BuildHasher`](core::hash::BuildHasher).
+            ///
+            /// Note that `build_hasher` is stored in the structure and as such it can
+            /// cause the type to stop being [`Send`] and [`Sync`] if the hasher is not.
+            /// It can also increase the size of the structure.
+            pub const fn new_with_build_hasher(value: T, build_hasher: BH) -> Self {
+                Self::new_with_sentinel(value, build_hasher, $crate::$default)
+            }
+
+            /// Creates a new wrapper with the given value and
+            /// [`BuildHasher`](core::hash::BuildHasher) and computes its hash right away.
+            ///
+            /// This is useful when the value is created on a different thread than
+            /// the one that will be hashing it (for example when inserting it into
+            /// a [`HashSet`](std::collections::HashSet)). The expensive hashing then happens
+            /// on the creating thread and the [`Hash`](core::hash::Hash) implementation
+            /// only loads the cached hash.
+            pub fn new_hashed_with_build_hasher(value: T, build_hasher: BH) -> Self
+            where
+                T: ::core::hash::Hash,
+            {
+                let this = Self::new_with_build_hasher(value, build_hasher);
+                Self::ensure_hashed(&this);
+                this
+            }
+
+            /// Creates a new wrapper from a value and its already computed internal hash.
+            ///
+            /// This is useful when the internal hash is known ahead of time, for example
+            /// when receiving values that were hashed by another process using the same
+            /// deterministic `build_hasher`. It is the inverse of [`Self::into_parts`].
+            ///
+            /// The hash is not checked. If it does not match the internal hash `build_hasher`
+            /// would compute for `value`, hash based collections will misbehave (though
+            /// not in an unsafe way). Use [`Self::verify_hash`] to check hashes
+            /// coming from untrusted sources.
+            pub fn from_parts(
+                value: T,
+                hash: <$crate::$default as $crate::SentinelPolicy>::Hash,
+                build_hasher: BH,
+            ) -> Self {
+                Self::from_parts_with_sentinel(value, hash, build_hasher, $crate::$default)
+            }
+        }
+
+        impl<T: ?Sized, BH: ::core::hash::BuildHasher, P: $crate::SentinelPolicy> $name<T, BH, P> {
+            /// Creates a new wrapper with the given value and
+            /// [`BuildHasher`](core::hash::BuildHasher) using the given
+            /// [`SentinelPolicy`](crate::SentinelPolicy).
+            ///
+            /// The policy decides how a hash that is not yet computed is represented.
+            /// See [`SentinelPolicy`](crate::SentinelPolicy) for the available choices.
+            pub const fn new_with_sentinel(value: T, build_hasher: BH, _sentinel: P) -> Self
+            where
+                T: Sized,
+            {
+                Self {
+                    value,
+                    hash: <P::$slot as $crate::sentinel::Slot<P::Hash>>::NONE,
+                    build_hasher,
+                }
+            }
+
+            /// Creates a new wrapper from a value and its already computed
+            /// internal hash using the given [`SentinelPolicy`](crate::SentinelPolicy).
+            ///
+            /// See [`Self::from_parts`] for details.
+            pub fn from_parts_with_sentinel(
+                value: T,
+                hash: P::Hash,
+                build_hasher: BH,
+                _sentinel: P,
+            ) -> Self
+            where
+                T: Sized,
+            {
+                Self {
+                    value,
+                    hash: <P::$slot as $crate::sentinel::Slot<P::Hash>>::new_some(hash),
+                    build_hasher,
+                }
+            }
+
+            /// Returns the cached internal hash if it is currently cached.
+            ///
+            /// Note that this is the internal hash computed by `BH`, not the hash
+            /// produced by the [`Hash`](core::hash::Hash) implementation of the wrapper
+            /// (which is the hash of the internal hash computed by the outer hasher).
+            #[inline]
+            #[must_use]
+            pub fn cached_hash(this: &Self) -> Option<P::Hash> {
+                this.hash.get()
+            }
+
+            /// Destructs the wrapper and returns the stored value, the cached
+            /// internal hash if any and the [`BuildHasher`](core::hash::BuildHasher).
+            ///
+            /// See also [`Self::take_value`] and [`Self::from_parts`].
+            #[inline]
+            #[must_use]
+            pub fn into_parts(this: Self) -> (T, Option<P::Hash>, BH)
+            where
+                T: Sized,
+            {
+                let hash = this.hash.get();
+                (this.value, hash, this.build_hasher)
+            }
+
+            /// Explicitly invalidates the cached hash. This should not be necessary
+            /// in most cases as the hash will be automatically invalidated when
+            /// the value is accessed mutably. However, if the value uses interior
+            /// mutability in a way that affects the hash, you will need to call
+            /// this function manually whenever the hash might have changed.
+            #[inline]
+            pub fn invalidate_hash(this: &mut Self) {
+                #[cfg(feature = "instrument")]
+                $crate::stats::explicit_invalidation::<BH>();
+                this.hash.set(None);
+            }
+
+            /// Destructs the wrapper and returns the stored value.
+            #[inline]
+            #[must_use]
+            #[allow(clippy::missing_const_for_fn)] // false positive, `this` might get dropped
+            pub fn take_value(this: Self) -> T
+            where
+                T: Sized,
+            {
+                this.value
+            }
+
+            /// Explicitly returns an immutable reference to the stored value.
+            ///
+            /// Most of the time this will not be necessary as the wrapper
+            /// implements [`Deref`](core::ops::Deref) so autoderef rules will
+            /// automatically dereference it to the stored value.
+            #[inline]
+            #[must_use]
+            pub const fn get(this: &Self) -> &T {
+                &this.value
+            }
+
+            /// Explicitly returns a mutable reference to the stored value and
+            /// invalidates the cached hash.
+            ///
+            /// Most of the time this will not be necessary as the wrapper implements
+            /// [`DerefMut`](core::ops::DerefMut) so autoderef rules will automatically
+            /// dereference it to the stored value. (Such dereference still
+            /// invalidates the stored hash.)
+            #[inline]
+            #[must_use]
+            pub fn get_mut(this: &mut Self) -> &mut T {
+                this.invalidate_for_mutation();
+                &mut this.value
+            }
+
+            /// Invalidates the cached hash because the value is about to be modified.
+            #[inline]
+            fn invalidate_for_mutation(&self) {
+                #[cfg(feature = "instrument")]
+                $crate::stats::mutable_invalidation::<BH>();
+                self.hash.set(None);
+            }
+        }
+
+        impl<
+                T: ::core::hash::Hash + ?Sized,
+                BH: ::core::hash::BuildHasher,
+                P: $crate::SentinelPolicy,
+            >
+            $name<T, BH, P>
+        {
+            /// Makes sure the hash is cached, computing it if necessary, and returns
+            /// the internal hash.
+            ///
+            /// This is the same as [`Self::internal_hash`] but reads better when
+            /// called only for its side effect.
+            #[inline]
+            pub fn ensure_hashed(this: &Self) -> P::Hash {
+                Self::internal_hash(this)
+            }
+
+            /// Returns the internal hash, computing and caching it if necessary.
+            ///
+            /// Note that this is the internal hash computed by `BH`, not the hash
+            /// produced by the [`Hash`](core::hash::Hash) implementation of the wrapper
+            /// (which is the hash of the internal hash computed by the outer hasher).
+            /// This makes it suitable for example for sharding or for building your
+            /// own hash tables on top of the cached hashes.
+            #[inline]
+            #[must_use]
+            pub fn internal_hash(this: &Self) -> P::Hash {
+                if let Some(hash) = this.hash.get() {
+                    #[cfg(feature = "instrument")]
+                    $crate::stats::hit::<BH>();
+                    #[cfg(feature = "verify")]
+                    $crate::verify::check_cached_hash::<T>(
+                        $crate::sentinel::InternalHash::to_u64(hash),
+                        $crate::sentinel::InternalHash::to_u64(
+                            $crate::cachedhash::compute_internal_hash::<_, _, P>(
+                                &this.value,
+                                &this.build_hasher,
+                            ),
+                        ),
+                    );
+                    return hash;
+                }
+                this.store_internal_hash()
+            }
+
+            /// Recomputes the internal hash and checks that it matches the cached one.
+            ///
+            /// Returns `true` if no hash is cached. The cached hash is left unchanged.
+            #[must_use]
+            pub fn verify_hash(this: &Self) -> bool {
+                match this.hash.get() {
+                    Some(hash) => {
+                        hash == $crate::cachedhash::compute_internal_hash::<_, _, P>(
+                            &this.value,
+                            &this.build_hasher,
+                        )
+                    }
+                    None => true,
+                }
+            }
+
+            /// Returns a guard giving mutable access to the stored value which eagerly
+            /// recomputes the hash when dropped.
+            ///
+            /// Unlike [`Self::get_mut`], which only invalidates the hash so that it gets
+            /// recomputed on the next [`Hash::hash`](core::hash::Hash::hash) call, this
+            /// makes sure the hash is already cached once the modification is done.
+            /// This is equivalent to [`Self::modify_with_policy`] with
+            /// [`RehashPolicy::Eager`](crate::RehashPolicy::Eager).
+            #[inline]
+            pub fn modify(this: &mut Self) -> $guard<'_, T, BH, P> {
+                Self::modify_with_policy(this, $crate::RehashPolicy::Eager)
+            }
+
+            /// Returns a guard giving mutable access to the stored value which handles
+            /// the cached hash according to `policy` when dropped.
+            ///
+            /// The hash is invalidated right away so that it is never stale even if
+            /// the guard is leaked.
+            #[inline]
+            pub fn modify_with_policy(
+                this: &mut Self,
+                policy: $crate::RehashPolicy,
+            ) -> $guard<'_, T, BH, P> {
+                this.invalidate_for_mutation();
+                $guard {
+                    cached_hash: this,
+                    policy,
+                    rehash: Self::store_internal_hash,
+                }
+            }
+
+            /// Modifies the stored value using `f` and eagerly recomputes the hash
+            /// afterwards. Returns the result of `f`.
+            ///
+            /// This is a shorthand for [`Self::modify`].
+            #[inline]
+            pub fn update<R>(this: &mut Self, f: impl FnOnce(&mut T) -> R) -> R {
+                f(&mut Self::modify(this))
+            }
+
+            /// Computes the internal hash of the stored value and caches it.
+            #[inline]
+            fn store_internal_hash(&self) -> P::Hash {
+                #[cfg(feature = "instrument")]
+                $crate::stats::miss::<BH>();
+                let hash = $crate::cachedhash::compute_internal_hash::<_, _, P>(
+                    &self.value,
+                    &self.build_hasher,
+                );
+                self.hash.set(Some(hash));
+                hash
+            }
+        }
+
+        impl<T: PartialEq + ?Sized, BH: ::core::hash::BuildHasher, P: $crate::SentinelPolicy>
+            PartialEq for $name<T, BH, P>
+        {
+            fn eq(&self, other: &Self) -> bool {
+                if $crate::cachedhash::cached_hashes_differ::<BH, _>(
+                    self.hash.get(),
+                    other.hash.get(),
+                ) {
+                    return false;
+                }
+                self.value == other.value
+            }
+        }
+
+        impl<T: Eq + ?Sized, BH: ::core::hash::BuildHasher, P: $crate::SentinelPolicy> Eq
+            for $name<T, BH, P>
+        {
+        }
+
+        impl<
+                T: ::core::hash::Hash + ?Sized,
+                BH: ::core::hash::BuildHasher,
+                P: $crate::SentinelPolicy,
+            >
+            ::core::hash::Hash for $name<T, BH, P>
+        {
+            fn hash<H2: ::core::hash::Hasher>(&self, state: &mut H2) {
+                let hash = if let Some(hash) = self.hash.get() {
+                    #[cfg(feature = "instrument")]
+                    $crate::stats::hit::<BH>();
+                    #[cfg(feature = "verify")]
+                    $crate::verify::check_cached_hash::<T>(
+                        $crate::sentinel::InternalHash::to_u64(hash),
+                        $crate::sentinel::InternalHash::to_u64(
+                            $crate::cachedhash::compute_internal_hash::<_, _, P>(
+                                &self.value,
+                                &self.build_hasher,
+                            ),
+                        ),
+                    );
+                    hash
+                } else {
+                    self.store_internal_hash()
+                };
+                $crate::sentinel::InternalHash::write_to(hash, state);
+            }
+        }
+
+        $(#[$guard_meta])*
+        #[must_use = "the hash is recomputed when the guard is dropped"]
+        pub struct $guard<
+            'a,
+            T: ?Sized,
+            BH: ::core::hash::BuildHasher,
+            P: $crate::SentinelPolicy = $crate::$default,
+        > {
+            cached_hash: &'a mut $name<T, BH, P>,
+            policy: $crate::RehashPolicy,
+            /// Recomputes the hash, captured where `T: Hash` is known because
+            /// `Drop` cannot have more bounds than the struct.
+            rehash: fn(&$name<T, BH, P>) -> P::Hash,
+        }
+
+        impl<T: ?Sized, BH: ::core::hash::BuildHasher, P: $crate::SentinelPolicy>
+            ::core::ops::Deref for $guard<'_, T, BH, P>
+        {
+            type Target = T;
+
+            #[inline]
+            fn deref(&self) -> &Self::Target {
+                &self.cached_hash.value
+            }
+        }
+
+        impl<T: ?Sized, BH: ::core::hash::BuildHasher, P: $crate::SentinelPolicy>
+            ::core::ops::DerefMut for $guard<'_, T, BH, P>
+        {
+            #[inline]
+            fn deref_mut(&mut self) -> &mut Self::Target {
+                &mut self.cached_hash.value
+            }
+        }
+
+        impl<T: ?Sized, BH: ::core::hash::BuildHasher, P: $crate::SentinelPolicy>
+            Drop for $guard<'_, T, BH, P>
+        {
+            fn drop(&mut self) {
+                if self.policy == $crate::RehashPolicy::Eager
+                    && !$crate::cachedhash::panicking()
+                {
+                    (self.rehash)(self.cached_hash);
+                }
+            }
+        }
+
+        impl<
+                T: ::core::fmt::Debug + ?Sized,
+                BH: ::core::hash::BuildHasher,
+                P: $crate::SentinelPolicy,
+            > ::core::fmt::Debug for $guard<'_, T, BH, P>
+        {
+            fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                f.debug_struct(stringify!($guard))
+                    .field("value", &&self.cached_hash.value)
+                    .field("policy", &self.policy)
+                    .finish()
+            }
+        }
+
+        impl<T: ?Sized, BH: ::core::hash::BuildHasher, P: $crate::SentinelPolicy> AsMut<T>
+            for $name<T, BH, P>
+        {
+            fn as_mut(&mut self) -> &mut T {
+                Self::get_mut(self)
+            }
+        }
+
+        impl<T: ?Sized, BH: ::core::hash::BuildHasher, P: $crate::SentinelPolicy> AsRef<T>
+            for $name<T, BH, P>
+        {
+            fn as_ref(&self) -> &T {
+                Self::get(self)
+            }
+        }
+
+        impl<T: ?Sized, BH: ::core::hash::BuildHasher, P: $crate::SentinelPolicy>
+            ::core::borrow::BorrowMut<T> for $name<T, BH, P>
+        {
+            fn borrow_mut(&mut self) -> &mut T {
+                Self::get_mut(self)
+            }
+        }
+
+        /// Note that the [`Hash`](core::hash::Hash) implementations of `T` and the
+        /// wrapper differ so this cannot be used to look up `&T` in hash based
+        /// collections. Use [`CachedHashMap`](crate::CachedHashMap) for that.
+        impl<T: ?Sized, BH: ::core::hash::BuildHasher, P: $crate::SentinelPolicy>
+            ::core::borrow::Borrow<T> for $name<T, BH, P>
+        {
+            fn borrow(&self) -> &T {
+                Self::get(self)
+            }
+        }
+
+        #[doc(hidden)]
+        #[cfg(feature = "std")]
+        impl<
+                'a,
+                T: ::core::hash::Hash + 'a,
+                BH: ::core::hash::BuildHasher + 'a,
+                P: $crate::SentinelPolicy + 'a,
+            >
+            ::core::borrow::Borrow<dyn $crate::collections::KeyQuery<T> + 'a> for $name<T, BH, P>
+        {
+            fn borrow(&self) -> &(dyn $crate::collections::KeyQuery<T> + 'a) {
+                self
+            }
+        }
+
+        #[cfg(feature = "std")]
+        impl<T: ::core::hash::Hash, BH: ::core::hash::BuildHasher, P: $crate::SentinelPolicy>
+            $crate::collections::KeyQuery<T> for $name<T, BH, P>
+        {
+            fn value(&self) -> &T {
+                &self.value
+            }
+
+            fn hash_internal(&self, mut state: &mut dyn ::core::hash::Hasher) {
+                ::core::hash::Hash::hash(self, &mut state);
+            }
+        }
+
+        #[cfg(feature = "std")]
+        impl<T, BH: ::core::hash::BuildHasher, P: $crate::SentinelPolicy>
+            $crate::collections::sealed::Sealed
+            for $name<T, BH, P>
+        {
+        }
+
+        impl<T: ?Sized, BH: ::core::hash::BuildHasher, P: $crate::SentinelPolicy> ::core::ops::Deref
+            for $name<T, BH, P>
+        {
+            type Target = T;
+
+            #[inline]
+            fn deref(&self) -> &Self::Target {
+                Self::get(self)
+            }
+        }
+
+        impl<T: ?Sized, BH: ::core::hash::BuildHasher, P: $crate::SentinelPolicy>
+            ::core::ops::DerefMut for $name<T, BH, P>
+        {
+            #[inline]
+            fn deref_mut(&mut self) -> &mut Self::Target {
+                Self::get_mut(self)
+            }
+        }
+
+        impl<T, H: ::core::hash::Hasher + Default, P: $crate::SentinelPolicy> From<T>
+            for $name<T, ::core::hash::BuildHasherDefault<H>, P>
+        {
+            fn from(value: T) -> Self {
+                Self::new_with_sentinel(
+                    value,
+                    ::core::hash::BuildHasherDefault::default(),
+                    P::default(),
+                )
+            }
+        }
+
+        impl<T: Clone, BH: ::core::hash::BuildHasher + Clone, P: $crate::SentinelPolicy> Clone
+            for $name<T, BH, P>
+        {
+            fn clone(&self) -> Self {
+                Self {
+                    value: self.value.clone(),
+                    hash: self.hash.clone(),
+                    build_hasher: self.build_hasher.clone(),
+                }
+            }
+        }
+    };
+}
+
+pub(crate) use impl_cached_hash;
+
+/// Generates the tests shared by all wrappers implemented by [`impl_cached_hash`]
+/// and their guards in a module named `shared_tests`.
+#[cfg(test)]
+macro_rules! cached_hash_tests {
+    ($name:ident, $guard:ident) => {
+        #[cfg(all(test, feature = "std"))]
+        mod shared_tests {
+            use std::collections::hash_map::{DefaultHasher, RandomState};
+            use std::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
+            use std::num::NonZeroU64;
+            use std::sync::atomic::AtomicBool;
+
+            use super::{$guard, $name};
+            use crate::RehashPolicy;
+
+            $crate::cachedhash::wrapper::cached_hash_common_tests!($name, cached_hash);
+            #[cfg(not(feature = "verify"))]
+            use common_tests::YouOnlyHashOnce;
+
+            fn calculate_hash<T: Hash>(t: &T) -> u64 {
+                calculate_hash_with_hasher::<T, DefaultHasher>(t)
+            }
+
+            fn calculate_hash_with_hasher<T: Hash, H: Hasher + Default>(t: &T) -> u64 {
+                let mut s = H::default();
+                t.hash(&mut s);
+                s.finish()
+            }
+
+            #[test]
+            fn hash_same_after_noop_mut_borrow() {
+                let mut foo = $name::new("foo".to_string());
+                let hash = calculate_hash(&foo);
+                let _ = $name::get_mut(&mut foo);
+                assert_eq!(hash, calculate_hash(&foo));
+            }
+
+            #[test]
+            fn hash_different_after_modification() {
+                let mut foo = $name::new("foo".to_string());
+                let hash = calculate_hash(&foo);
+                foo.push('a');
+                // The first three lines are there to make sure the test doesn't start
+                // failing if the standard library hashing changes to create a collision
+                // in the test case (0 gets internally converted to 1). This way it would
+                // only make this test useless.
+                assert!(
+                    calculate_hash(&"foo".to_string()) == 0
+                        || calculate_hash(&"fooa".to_string()) == 0
+                        || calculate_hash(&"foo".to_string())
+                            == calculate_hash(&"fooa".to_string())
+                        || hash != calculate_hash(&foo)
+                );
+            }
+
+            #[test]
+            fn hash_same_as_cachedhash() {
+                let foo = $name::new("foo".to_string());
+                let bar = crate::CachedHash::new("foo".to_string());
+                assert_eq!(calculate_hash(&foo), calculate_hash(&bar));
+            }
+
+            #[test]
+            #[cfg(not(feature = "verify"))] // `verify` rehashes on every use
+            fn new_hashed_hashes_only_at_construction() {
+                let foo = $name::new_hashed(YouOnlyHashOnce::new());
+                assert!(foo.hash.get().is_some());
+                assert!(foo.hashed_once.load(std::sync::atomic::Ordering::SeqCst));
+                calculate_hash(&foo);
+                calculate_hash(&foo);
+                assert_eq!($name::ensure_hashed(&foo), foo.hash.get().unwrap());
+            }
+
+            #[test]
+            #[cfg(not(feature = "verify"))] // `verify` rehashes on every use
+            fn new_hashed_on_another_thread() {
+                let foo = std::thread::spawn(|| $name::new_hashed(YouOnlyHashOnce::new()))
+                    .join()
+                    .unwrap();
+                calculate_hash(&foo);
+                calculate_hash(&foo);
+            }
+
+            #[test]
+            fn new_hashed_same_as_lazy() {
+                let foo = $name::new_hashed("foo".to_string());
+                let bar = $name::new("foo".to_string());
+                assert_eq!(calculate_hash(&foo), calculate_hash(&bar));
+                let baz: $name<_, BuildHasherDefault<nohash_hasher::NoHashHasher<u64>>> =
+                    $name::new_hashed_with_hasher(42u64);
+                assert_eq!(baz.hash.get(), NonZeroU64::new(42));
+            }
+
+            #[test]
+            fn ensure_hashed_caches() {
+                let mut foo = $name::new("foo".to_string());
+                assert!(foo.hash.get().is_none());
+                let hash = $name::ensure_hashed(&foo);
+                assert_eq!(foo.hash.get(), Some(hash));
+                foo.push('a');
+                assert!(foo.hash.get().is_none());
+                assert_ne!($name::ensure_hashed(&foo), hash);
+            }
+
+            #[test]
+            fn modify_recomputes_eagerly() {
+                let mut foo = $name::new("foo".to_string());
+                let hash = calculate_hash(&foo);
+                {
+                    let mut guard = $name::modify(&mut foo);
+                    assert!(guard.cached_hash.hash.get().is_none());
+                    guard.push('a');
+                }
+                assert!(foo.hash.get().is_some());
+                assert_eq!(
+                    calculate_hash(&foo),
+                    calculate_hash(&$name::new("fooa".to_string()))
+                );
+                $name::get_mut(&mut foo).pop();
+                assert_eq!(hash, calculate_hash(&foo));
+            }
+
+            #[test]
+            fn modify_lazy_leaves_hash_invalidated() {
+                let mut foo = $name::new("foo".to_string());
+                calculate_hash(&foo);
+                $name::modify_with_policy(&mut foo, RehashPolicy::Lazy).push('a');
+                assert!(foo.hash.get().is_none());
+                assert_eq!(
+                    calculate_hash(&foo),
+                    calculate_hash(&$name::new("fooa".to_string()))
+                );
+            }
+
+            #[test]
+            fn leaked_guard_leaves_hash_invalidated() {
+                let mut foo = $name::new("foo".to_string());
+                calculate_hash(&foo);
+                let mut guard = $name::modify(&mut foo);
+                guard.push('a');
+                std::mem::forget(guard);
+                assert!(foo.hash.get().is_none());
+            }
+
+            #[test]
+            fn update_recomputes_eagerly() {
+                let mut foo = $name::new("foo".to_string());
+                let len = $name::update(&mut foo, |value| {
+                    value.push('a');
+                    value.len()
+                });
+                assert_eq!(len, 4);
+                assert!(foo.hash.get().is_some());
+                assert_eq!(
+                    calculate_hash(&foo),
+                    calculate_hash(&$name::new("fooa".to_string()))
+                );
+            }
+
+            #[test]
+            fn update_panic_leaves_hash_invalidated() {
+                let mut foo = $name::new("foo".to_string());
+                calculate_hash(&foo);
+                let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                    $name::update(&mut foo, |value| {
+                        value.push('a');
+                        panic!("oops");
+                    });
+                }));
+                assert!(result.is_err());
+                assert!(foo.hash.get().is_none());
+                assert_eq!(*foo, "fooa");
+            }
+
+            #[test]
+            fn cached_and_internal_hash() {
+                let mut foo = $name::new("foo".to_string());
+                assert_eq!($name::cached_hash(&foo), None);
+                let hash = $name::internal_hash(&foo);
+                assert_eq!($name::cached_hash(&foo), Some(hash));
+                assert_eq!(
+                    hash.get(),
+                    BuildHasherDefault::<DefaultHasher>::default().hash_one("foo")
+                );
+                assert_eq!(calculate_hash(&hash.get()), calculate_hash(&foo));
+                foo.push('a');
+                assert_eq!($name::cached_hash(&foo), None);
+                assert_ne!($name::internal_hash(&foo), hash);
+            }
+
+            #[test]
+            fn from_parts_and_into_parts() {
+                let foo = $name::new_hashed("foo".to_string());
+                let (value, hash, build_hasher) = $name::into_parts(foo);
+                assert_eq!(value, "foo");
+                let hash = hash.unwrap();
+                let bar = $name::from_parts(value, hash, build_hasher);
+                assert_eq!($name::cached_hash(&bar), Some(hash));
+                assert!($name::verify_hash(&bar));
+                assert_eq!(
+                    calculate_hash(&bar),
+                    calculate_hash(&$name::new("foo".to_string()))
+                );
+                let (_, hash, _) = $name::into_parts($name::new(1));
+                assert_eq!(hash, None);
+            }
+
+            #[test]
+            fn verify_hash_detects_wrong_hash() {
+                let foo = $name::from_parts(
+                    "foo".to_string(),
+                    NonZeroU64::new(42).unwrap(),
+                    BuildHasherDefault::<DefaultHasher>::default(),
+                );
+                assert!(!$name::verify_hash(&foo));
+                assert_eq!($name::cached_hash(&foo), NonZeroU64::new(42));
+                assert!($name::verify_hash(&$name::new("foo".to_string())));
+            }
+
+            /// Only implements [`Hash`], like types containing floats.
+            #[derive(Debug)]
+            struct HashOnly(f64);
+
+            impl Hash for HashOnly {
+                fn hash<H: Hasher>(&self, state: &mut H) {
+                    self.0.to_bits().hash(state);
+                }
+            }
+
+            /// Only implements [`Eq`].
+            #[derive(Debug, PartialEq, Eq)]
+            struct EqOnly(u8);
+
+            /// Holds a wrapper without repeating any bounds.
+            struct Holder<T> {
+                inner: $name<T>,
+            }
+
+            #[test]
+            fn hash_only_payload() {
+                let mut foo = $name::new_hashed(HashOnly(1.5));
+                let hash = calculate_hash(&foo);
+                assert_eq!(
+                    $name::cached_hash(&foo),
+                    NonZeroU64::new(calculate_hash(&HashOnly(1.5)))
+                );
+                $name::update(&mut foo, |value| value.0 = 2.5);
+                assert_ne!(calculate_hash(&foo), hash);
+                foo.0 = 1.5;
+                assert_eq!($name::cached_hash(&foo), None);
+                assert_eq!(calculate_hash(&foo), hash);
+            }
+
+            #[test]
+            fn eq_only_payload() {
+                let mut foo = $name::new(EqOnly(1));
+                assert_eq!(foo, $name::new(EqOnly(1)));
+                foo.0 = 2;
+                assert_ne!(foo, $name::new(EqOnly(1)));
+                assert_eq!($name::cached_hash(&foo), None);
+                assert_eq!($name::take_value(foo), EqOnly(2));
+            }
+
+            #[test]
+            fn generic_holder_without_bounds() {
+                fn wrap<T>(value: T) -> Holder<T> {
+                    Holder {
+                        inner: $name::new(value),
+                    }
+                }
+
+                let holder = wrap(EqOnly(3));
+                assert_eq!(*holder.inner, EqOnly(3));
+                let holder = wrap(HashOnly(3.0));
+                assert_eq!(
+                    $name::internal_hash(&holder.inner),
+                    $name::internal_hash(&$name::new(HashOnly(3.0)))
+                );
+            }
+
+            #[test]
+            fn generic_guard_without_bounds() {
+                fn peek<T: Clone>(guard: &$guard<'_, T, BuildHasherDefault<DefaultHasher>>) -> T {
+                    (**guard).clone()
+                }
+
+                let mut foo = $name::new("foo".to_string());
+                let guard = $name::modify(&mut foo);
+                assert_eq!(peek(&guard), "foo");
+                drop(guard);
+                assert!($name::cached_hash(&foo).is_some());
+            }
+
+            #[test]
+            fn struct_is_small() {
+                assert!(
+                    std::mem::size_of::<$name<String>>()
+                        <= std::mem::size_of::<String>() + std::mem::size_of::<u64>()
+                );
+            }
+
+            /// A value whose internal hash under [`NoHashHasher`](nohash_hasher::NoHashHasher)
+            /// is the stored number.
+            #[derive(Debug)]
+            struct Number(u64, AtomicBool);
+            impl Number {
+                const fn new(value: u64) -> Self {
+                    Self(value, AtomicBool::new(false))
+                }
+                fn was_compared(&self) -> bool {
+                    self.1.load(std::sync::atomic::Ordering::SeqCst)
+                }
+            }
+            impl Eq for Number {}
+            impl PartialEq for Number {
+                fn eq(&self, other: &Self) -> bool {
+                    self.1.store(true, std::sync::atomic::Ordering::SeqCst);
+                    self.0 == other.0
+                }
+            }
+            impl Hash for Number {
+                fn hash<H: Hasher>(&self, state: &mut H) {
+                    state.write_u64(self.0);
+                }
+            }
+
+            type NoHashBuild = BuildHasherDefault<nohash_hasher::NoHashHasher<u64>>;
+
+            #[test]
+            fn eq_short_circuits_on_different_hashes() {
+                let foo = $name::<_, NoHashBuild>::new_with_hasher(Number::new(2));
+                let bar = $name::<_, NoHashBuild>::new_with_hasher(Number::new(3));
+                calculate_hash(&foo);
+                calculate_hash(&bar);
+                assert_ne!(foo, bar);
+                assert!(!foo.was_compared());
+            }
+
+            #[test]
+            fn eq_compares_values_without_hashes() {
+                let foo = $name::<_, NoHashBuild>::new_with_hasher(Number::new(2));
+                let bar = $name::<_, NoHashBuild>::new_with_hasher(Number::new(3));
+                calculate_hash(&foo);
+                assert_ne!(foo, bar);
+                assert!(foo.was_compared());
+                let baz = $name::<_, NoHashBuild>::new_with_hasher(Number::new(2));
+                assert_eq!(foo, baz);
+            }
+
+            #[test]
+            fn eq_compares_values_on_zero_hash_collision() {
+                let zero = $name::<_, NoHashBuild>::new_with_hasher(Number::new(0));
+                let one = $name::<_, NoHashBuild>::new_with_hasher(Number::new(1));
+                calculate_hash(&zero);
+                calculate_hash(&one);
+                assert_eq!(zero.hash.get(), one.hash.get());
+                assert_ne!(zero, one);
+                assert!(zero.was_compared());
+                let other_zero = $name::<_, NoHashBuild>::new_with_hasher(Number::new(0));
+                calculate_hash(&other_zero);
+                assert_eq!(zero, other_zero);
+            }
+
+            #[test]
+            fn eq_does_not_short_circuit_with_stateful_build_hasher() {
+                let foo = $name::new_with_build_hasher("foo".to_string(), RandomState::new());
+                let bar = $name::new_with_build_hasher("foo".to_string(), RandomState::new());
+                calculate_hash(&foo);
+                calculate_hash(&bar);
+                assert_eq!(foo, bar);
+            }
+
+            #[test]
+            fn eq_does_not_short_circuit_with_no_eq_shortcut() {
+                let build = || crate::NoEqShortcut::new(NoHashBuild::default());
+                let foo = $name::new_with_build_hasher(Number::new(2), build());
+                let bar = $name::new_with_build_hasher(Number::new(3), build());
+                calculate_hash(&foo);
+                calculate_hash(&bar);
+                assert_ne!(foo, bar);
+                assert!(foo.was_compared());
+                assert_eq!(
+                    $name::internal_hash(&foo),
+                    $name::internal_hash(&$name::<_, NoHashBuild>::new_with_hasher(Number::new(2)))
+                );
+            }
+
+            #[test]
+            fn zero_hash() {
+                use nohash_hasher::NoHashHasher;
+
+                struct FixedHash<const H: u64>();
+                impl<const H: u64> Eq for FixedHash<H> {}
+                impl<const H: u64> PartialEq for FixedHash<H> {
+                    fn eq(&self, _other: &Self) -> bool {
+                        true
+                    }
+                }
+                impl<const H: u64> Hash for FixedHash<H> {
+                    fn hash<HS: Hasher>(&self, state: &mut HS) {
+                        state.write_u64(H);
+                    }
+                }
+
+                assert!(
+                    calculate_hash_with_hasher::<FixedHash<0>, NoHashHasher<u64>>(&FixedHash::<0>())
+                        == 0
+                );
+                let foo: $name<_, NoHashBuild> = $name::new_with_hasher(FixedHash::<0>());
+                let _ = calculate_hash(&foo);
+                assert!(foo.hash.get().is_some());
+            }
+
+            /// Instantiates every trait implementation with non-default
+            /// [`BuildHasher`]s so that none of them gets accidentally tied to the
+            /// default one.
+            mod non_default_hasher {
+                use std::borrow::{Borrow, BorrowMut};
+                use std::collections::hash_map::RandomState;
+                use std::collections::{BTreeSet, HashSet};
+                use std::fmt::Debug;
+                use std::hash::Hash;
+                use std::ops::{Deref, DerefMut};
+
+                use super::{$name, NoHashBuild};
+                use crate::{BumpZero, NoSentinel, RemixZero, SentinelPolicy};
+
+                const fn assert_impls<W, T>()
+                where
+                    W: PartialEq
+                        + Eq
+                        + PartialOrd
+                        + Ord
+                        + Hash
+                        + Debug
+                        + Clone
+                        + AsRef<T>
+                        + AsMut<T>
+                        + Borrow<T>
+                        + BorrowMut<T>
+                        + Deref<Target = T>
+                        + DerefMut,
+                {
+                }
+
+                const fn assert_from<W: From<T>, T>() {}
+
+                /// Checks every trait implementation for all [`SentinelPolicy`]s
+                /// with both a default constructible and a stateful [`BuildHasher`].
+                ///
+                /// [`BuildHasher`]: std::hash::BuildHasher
+                const fn assert_all_impls<P: SentinelPolicy>() {
+                    assert_impls::<$name<u64, NoHashBuild, P>, u64>();
+                    assert_from::<$name<u64, NoHashBuild, P>, u64>();
+                    assert_impls::<$name<u64, RandomState, P>, u64>();
+                }
+
+                #[test]
+                fn all_impls_are_generic_over_build_hasher_and_sentinel() {
+                    assert_all_impls::<BumpZero>();
+                    assert_all_impls::<RemixZero>();
+                    assert_all_impls::<NoSentinel>();
+                }
+
+                #[test]
+                #[allow(clippy::mutable_key_type)]
+                fn ordered_collections_with_custom_hasher() {
+                    let mut set = BTreeSet::new();
+                    for value in [3u64, 1, 2] {
+                        set.insert($name::<_, NoHashBuild>::new_with_hasher(value));
+                    }
+                    let values = set.into_iter().map($name::take_value).collect::<Vec<_>>();
+                    assert_eq!(values, vec![1, 2, 3]);
+
+                    let state = RandomState::new();
+                    let a = $name::new_with_build_hasher(1u64, state.clone());
+                    let b = $name::new_with_build_hasher(2u64, state);
+                    assert!(a < b);
+                    assert_eq!(a.cmp(&b), std::cmp::Ordering::Less);
+                }
+
+                #[test]
+                #[allow(clippy::mutable_key_type)]
+                fn hashed_collections_with_custom_hasher() {
+                    let mut set = HashSet::new();
+                    set.insert($name::<_, NoHashBuild>::new_with_hasher(1u64));
+                    assert!(set.contains(&$name::new_with_hasher(1u64)));
+                    assert!(!set.contains(&$name::new_with_hasher(2u64)));
+                }
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+pub(crate) use cached_hash_tests;
+
+/// Generates the tests of the basic caching behaviour in a module named
+/// `common_tests`.
+///
+/// Besides the wrappers implemented by [`impl_cached_hash`] these are shared by
+/// the other variants. `$name` needs `new`, `invalidate_hash` and `take_value`,
+/// dereference mutably to `String` and implement [`Clone`] and [`Hash`](core::hash::Hash).
+/// `$cached` is the associated function returning the currently cached hash if
+/// any.
+#[cfg(test)]
+macro_rules! cached_hash_common_tests {
+    ($name:ident, $cached:ident) => {
+        #[cfg(all(test, feature = "std"))]
+        mod common_tests {
+            use std::collections::hash_map::DefaultHasher;
+            use std::hash::{Hash, Hasher};
+            use std::sync::atomic::AtomicBool;
+
+            use super::$name;
+
+            fn calculate_hash<T: Hash>(t: &T) -> u64 {
+                let mut s = DefaultHasher::default();
+                t.hash(&mut s);
+                s.finish()
+            }
+
+            #[test]
+            fn hash_same_consecutive() {
+                let foo = $name::new("foo".to_string());
+                let hash = calculate_hash(&foo);
+                assert_eq!(hash, calculate_hash(&foo));
+            }
+
+            #[test]
+            #[allow(clippy::redundant_clone)]
+            fn hash_same_after_clone() {
+                let foo = $name::new("foo".to_string());
+                let hash = calculate_hash(&foo);
+                let foo2 = foo.clone();
+                assert_eq!($name::$cached(&foo2), $name::$cached(&foo));
+                assert_eq!(hash, calculate_hash(&foo2));
+            }
+
+            #[test]
+            fn hash_same_after_invalidation() {
+                let mut foo = $name::new("foo".to_string());
+                let hash = calculate_hash(&foo);
+                $name::invalidate_hash(&mut foo);
+                assert_eq!($name::$cached(&foo), None);
+                assert_eq!(hash, calculate_hash(&foo));
+            }
+
+            #[test]
+            fn invalidate_invalidates() {
+                let mut foo = $name::new("foo".to_string());
+                assert!($name::$cached(&foo).is_none());
+                calculate_hash(&foo);
+                assert!($name::$cached(&foo).is_some());
+                $name::invalidate_hash(&mut foo);
+                assert!($name::$cached(&foo).is_none());
+                calculate_hash(&foo);
+                assert!($name::$cached(&foo).is_some());
+            }
+
+            #[test]
+            fn mut_deref_invalidates() {
+                let mut foo = $name::new("foo".to_string());
+                calculate_hash(&foo);
+                let hash = $name::$cached(&foo);
+                assert!(hash.is_some());
+                foo.push('a');
+                assert!($name::$cached(&foo).is_none());
+                calculate_hash(&foo);
+                assert!($name::$cached(&foo).is_some());
+                assert_ne!($name::$cached(&foo), hash);
+                let _ = foo.len();
+                assert!($name::$cached(&foo).is_some());
+            }
+
+            #[cfg_attr(feature = "verify", allow(dead_code))]
+            pub struct YouOnlyHashOnce {
+                pub hashed_once: AtomicBool,
+            }
+            #[cfg_attr(feature = "verify", allow(dead_code))]
+            impl YouOnlyHashOnce {
+                pub const fn new() -> Self {
+                    Self {
+                        hashed_once: AtomicBool::new(false),
+                    }
+                }
+            }
+            impl Eq for YouOnlyHashOnce {}
+            impl PartialEq for YouOnlyHashOnce {
+                fn eq(&self, _other: &Self) -> bool {
+                    true
+                }
+            }
+            impl Hash for YouOnlyHashOnce {
+                fn hash<H: Hasher>(&self, _state: &mut H) {
+                    assert!(
+                        !self
+                            .hashed_once
+                            .swap(true, std::sync::atomic::Ordering::SeqCst),
+                        "Hashing should only happen once"
+                    );
+                }
+            }
+
+            #[test]
+            #[cfg(not(feature = "verify"))] // `verify` rehashes on every use
+            fn hash_gets_cached() {
+                let foo = $name::new(YouOnlyHashOnce::new());
+                calculate_hash(&foo);
+                calculate_hash(&foo);
+                calculate_hash(&foo);
+            }
+
+            #[test]
+            fn take_value() {
+                let foo = $name::new("foo".to_string());
+                assert_eq!($name::take_value(foo), "foo".to_string());
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+pub(crate) use cached_hash_common_tests;
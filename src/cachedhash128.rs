@@ -0,0 +1,439 @@
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
+use std::ops::{Deref, DerefMut};
+use std::sync::OnceLock;
+
+/// A [`Hasher`] that can produce a 128-bit hash.
+pub trait Hasher128: Hasher {
+    /// Returns the 128-bit hash value for the values written so far.
+    fn finish_u128(&self) -> u128;
+}
+
+/// A [`Hasher128`] built from two instances of a 64-bit [`Hasher`].
+///
+/// The second hasher is seeded by writing a fixed value into it before
+/// anything else so that the two halves of the resulting 128-bit hash are
+/// computed differently. This is a simple way to get a wider fingerprint out
+/// of an existing hasher but it is not a cryptographic hash.
+#[derive(Debug, Clone)]
+pub struct DoubleHasher<H> {
+    first: H,
+    second: H,
+}
+
+impl<H: Hasher + Default> Default for DoubleHasher<H> {
+    fn default() -> Self {
+        let mut second = H::default();
+        second.write_u64(0x9e37_79b9_7f4a_7c15);
+        Self {
+            first: H::default(),
+            second,
+        }
+    }
+}
+
+impl<H: Hasher> Hasher for DoubleHasher<H> {
+    fn write(&mut self, bytes: &[u8]) {
+        self.first.write(bytes);
+        self.second.write(bytes);
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.first.write_u64(i);
+        self.second.write_u64(i);
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.first.write_usize(i);
+        self.second.write_usize(i);
+    }
+
+    fn finish(&self) -> u64 {
+        self.first.finish()
+    }
+}
+
+impl<H: Hasher> Hasher128 for DoubleHasher<H> {
+    fn finish_u128(&self) -> u128 {
+        (u128::from(self.first.finish()) << 64) | u128::from(self.second.finish())
+    }
+}
+
+/// A version of [`CachedHash`](struct@crate::CachedHash) that caches a 128-bit
+/// fingerprint of the stored value instead of a 64-bit hash.
+///
+/// With billions of values a 64-bit internal hash is likely to have collisions
+/// which is a problem when the hash is used to deduplicate values. [`CachedHash128`]
+/// computes the fingerprint using a [`BuildHasher`] whose hashers implement
+/// [`Hasher128`] (by default [`DoubleHasher<DefaultHasher>`](DoubleHasher)) and
+/// caches it. The cached fingerprint is invalidated in the same situations as
+/// the hash of [`CachedHash`](struct@crate::CachedHash).
+///
+/// The fingerprint is stored in a [`OnceLock`] so there is no sentinel value
+/// and no forced collision of zero with another value, but the structure is
+/// larger than [`CachedHash`](struct@crate::CachedHash).
+///
+/// The [`Hash`] implementation writes both 64-bit halves of the fingerprint
+/// into the hasher so it cannot be used with
+/// [`PassThroughHasher`](crate::PassThroughHasher).
+///
+/// # Equality
+///
+/// As with [`CachedHash`](struct@crate::CachedHash), [`PartialEq`] returns `false`
+/// without comparing the values if both fingerprints are cached and differ
+/// (and `BH` is zero-sized). If you accept a probabilistic equality check you
+/// can use [`CachedHash128::probably_eq`] which only compares the fingerprints.
+///
+/// Only available with the `std` feature.
+#[derive(Debug)]
+pub struct CachedHash128<T, BH: BuildHasher = BuildHasherDefault<DoubleHasher<DefaultHasher>>>
+where
+    BH::Hasher: Hasher128,
+{
+    value: T,
+    fingerprint: OnceLock<u128>,
+    build_hasher: BH,
+}
+
+impl<T> CachedHash128<T> {
+    /// Creates a new [`CachedHash128`] with the given value using
+    /// [`DoubleHasher<DefaultHasher>`](DoubleHasher).
+    pub fn new(value: T) -> Self {
+        Self::new_with_hasher(value)
+    }
+}
+
+impl<T, H: Hasher128 + Default> CachedHash128<T, BuildHasherDefault<H>> {
+    /// Creates a new [`CachedHash128`] with the given value using a provided
+    /// hasher type implementing [`Hasher128`] and [`Default`].
+    pub fn new_with_hasher(value: T) -> Self {
+        Self::new_with_build_hasher(value, BuildHasherDefault::default())
+    }
+}
+
+impl<T, BH: BuildHasher> CachedHash128<T, BH>
+where
+    BH::Hasher: Hasher128,
+{
+    /// Creates a new [`CachedHash128`] with the given value and [`BuildHasher`].
+    pub const fn new_with_build_hasher(value: T, build_hasher: BH) -> Self {
+        Self {
+            value,
+            fingerprint: OnceLock::new(),
+            build_hasher,
+        }
+    }
+
+    /// Explicitly invalidates the cached fingerprint. See
+    /// [`CachedHash::invalidate_hash`](crate::CachedHash::invalidate_hash).
+    #[inline]
+    pub fn invalidate_hash(this: &mut Self) {
+        #[cfg(feature = "instrument")]
+        crate::stats::explicit_invalidation::<BH>();
+        this.fingerprint.take();
+    }
+
+    /// Destructs the [`CachedHash128`] and returns the stored value.
+    #[inline]
+    #[must_use]
+    #[allow(clippy::missing_const_for_fn)] // false positive, `this` might get dropped
+    pub fn take_value(this: Self) -> T {
+        this.value
+    }
+
+    /// Explicitly returns an immutable reference to the stored value.
+    #[inline]
+    #[must_use]
+    pub const fn get(this: &Self) -> &T {
+        &this.value
+    }
+
+    /// Explicitly returns a mutable reference to the stored value and
+    /// invalidates the cached fingerprint.
+    #[inline]
+    #[must_use]
+    pub fn get_mut(this: &mut Self) -> &mut T {
+        #[cfg(feature = "instrument")]
+        crate::stats::mutable_invalidation::<BH>();
+        this.fingerprint.take();
+        &mut this.value
+    }
+
+    /// Returns the 128-bit fingerprint of the stored value, computing and
+    /// caching it if necessary.
+    #[inline]
+    #[must_use]
+    pub fn fingerprint(this: &Self) -> u128
+    where
+        T: Hash,
+    {
+        if let Some(&fingerprint) = this.fingerprint.get() {
+            #[cfg(feature = "instrument")]
+            crate::stats::hit::<BH>();
+            #[cfg(feature = "verify")]
+            check_fingerprint::<T>(
+                fingerprint,
+                compute_fingerprint(&this.value, &this.build_hasher),
+            );
+            return fingerprint;
+        }
+        *this.fingerprint.get_or_init(|| {
+            #[cfg(feature = "instrument")]
+            crate::stats::miss::<BH>();
+            compute_fingerprint(&this.value, &this.build_hasher)
+        })
+    }
+
+    /// Returns the cached fingerprint if it is currently cached.
+    #[inline]
+    #[must_use]
+    pub fn cached_fingerprint(this: &Self) -> Option<u128> {
+        this.fingerprint.get().copied()
+    }
+
+    /// Compares the fingerprints of two values, computing them if necessary,
+    /// without comparing the values themselves.
+    ///
+    /// Unlike [`PartialEq`] this can return `true` for different values if
+    /// their fingerprints collide. This is unlikely for a good 128-bit hasher
+    /// but it is not impossible. Only use this if you accept such a
+    /// probabilistic equality check.
+    #[inline]
+    #[must_use]
+    pub fn probably_eq(this: &Self, other: &Self) -> bool
+    where
+        T: Hash,
+    {
+        Self::fingerprint(this) == Self::fingerprint(other)
+    }
+}
+
+/// Computes the 128-bit fingerprint of `value`.
+#[inline]
+fn compute_fingerprint<T: Hash + ?Sized, BH: BuildHasher>(value: &T, build_hasher: &BH) -> u128
+where
+    BH::Hasher: Hasher128,
+{
+    let mut hasher = build_hasher.build_hasher();
+    value.hash(&mut hasher);
+    hasher.finish_u128()
+}
+
+/// Reports the first differing 64-bit half of a stale cached fingerprint with
+/// [`check_cached_hash`](crate::verify::check_cached_hash).
+#[cfg(feature = "verify")]
+#[inline]
+#[allow(clippy::cast_possible_truncation)]
+fn check_fingerprint<T: ?Sized>(cached: u128, actual: u128) {
+    let shift = if cached >> 64 == actual >> 64 { 0 } else { 64 };
+    crate::verify::check_cached_hash::<T>((cached >> shift) as u64, (actual >> shift) as u64);
+}
+
+impl<T: PartialOrd, BH: BuildHasher> PartialOrd for CachedHash128<T, BH>
+where
+    BH::Hasher: Hasher128,
+{
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.value.partial_cmp(&other.value)
+    }
+}
+
+impl<T: Ord, BH: BuildHasher> Ord for CachedHash128<T, BH>
+where
+    BH::Hasher: Hasher128,
+{
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.value.cmp(&other.value)
+    }
+}
+
+impl<T: PartialEq, BH: BuildHasher> PartialEq for CachedHash128<T, BH>
+where
+    BH::Hasher: Hasher128,
+{
+    fn eq(&self, other: &Self) -> bool {
+        if std::mem::size_of::<BH>() == 0 {
+            if let (Some(fingerprint), Some(other_fingerprint)) =
+                (self.fingerprint.get(), other.fingerprint.get())
+            {
+                if fingerprint != other_fingerprint {
+                    return false;
+                }
+            }
+        }
+        self.value == other.value
+    }
+}
+
+impl<T: Eq, BH: BuildHasher> Eq for CachedHash128<T, BH> where BH::Hasher: Hasher128 {}
+
+impl<T: Hash, BH: BuildHasher> Hash for CachedHash128<T, BH>
+where
+    BH::Hasher: Hasher128,
+{
+    fn hash<H2: Hasher>(&self, state: &mut H2) {
+        let fingerprint = Self::fingerprint(self);
+        #[allow(clippy::cast_possible_truncation)]
+        {
+            state.write_u64((fingerprint >> 64) as u64);
+            state.write_u64(fingerprint as u64);
+        }
+    }
+}
+
+impl<T, BH: BuildHasher> AsMut<T> for CachedHash128<T, BH>
+where
+    BH::Hasher: Hasher128,
+{
+    fn as_mut(&mut self) -> &mut T {
+        Self::get_mut(self)
+    }
+}
+
+impl<T, BH: BuildHasher> AsRef<T> for CachedHash128<T, BH>
+where
+    BH::Hasher: Hasher128,
+{
+    fn as_ref(&self) -> &T {
+        Self::get(self)
+    }
+}
+
+impl<T, BH: BuildHasher> Deref for CachedHash128<T, BH>
+where
+    BH::Hasher: Hasher128,
+{
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        Self::get(self)
+    }
+}
+
+impl<T, BH: BuildHasher> DerefMut for CachedHash128<T, BH>
+where
+    BH::Hasher: Hasher128,
+{
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        Self::get_mut(self)
+    }
+}
+
+impl<T, H: Hasher128 + Default> From<T> for CachedHash128<T, BuildHasherDefault<H>> {
+    fn from(value: T) -> Self {
+        Self::new_with_hasher(value)
+    }
+}
+
+impl<T: Clone, BH: BuildHasher + Clone> Clone for CachedHash128<T, BH>
+where
+    BH::Hasher: Hasher128,
+{
+    fn clone(&self) -> Self {
+        Self {
+            value: self.value.clone(),
+            fingerprint: self.fingerprint.clone(),
+            build_hasher: self.build_hasher.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+crate::cachedhash::wrapper::cached_hash_common_tests!(CachedHash128, cached_fingerprint);
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+
+    /// A [`Hasher128`] whose fingerprint is the last `u64` written into it.
+    #[derive(Default)]
+    struct IdentityHasher128(u64);
+
+    impl Hasher for IdentityHasher128 {
+        fn write(&mut self, _bytes: &[u8]) {
+            unreachable!("IdentityHasher128 only accepts write_u64")
+        }
+
+        fn write_u64(&mut self, i: u64) {
+            self.0 = i;
+        }
+
+        fn finish(&self) -> u64 {
+            self.0
+        }
+    }
+
+    impl Hasher128 for IdentityHasher128 {
+        fn finish_u128(&self) -> u128 {
+            u128::from(self.0)
+        }
+    }
+
+    type IdentityCachedHash128<T> = CachedHash128<T, BuildHasherDefault<IdentityHasher128>>;
+
+    #[derive(Debug)]
+    struct Number(u64, AtomicUsize);
+    impl Number {
+        const fn new(value: u64) -> Self {
+            Self(value, AtomicUsize::new(0))
+        }
+        fn comparisons(&self) -> usize {
+            self.1.load(std::sync::atomic::Ordering::SeqCst)
+        }
+    }
+    impl Eq for Number {}
+    impl PartialEq for Number {
+        fn eq(&self, other: &Self) -> bool {
+            self.1.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            self.0 == other.0
+        }
+    }
+    impl Hash for Number {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            state.write_u64(self.0);
+        }
+    }
+
+    #[test]
+    fn halves_differ() {
+        let foo = CachedHash128::new("foo".to_string());
+        let fingerprint = CachedHash128::fingerprint(&foo);
+        #[allow(clippy::cast_possible_truncation)]
+        let (high, low) = ((fingerprint >> 64) as u64, fingerprint as u64);
+        assert_ne!(high, low);
+        assert_eq!(
+            high,
+            BuildHasherDefault::<DefaultHasher>::default().hash_one("foo")
+        );
+    }
+
+    #[test]
+    fn zero_fingerprint_does_not_collide() {
+        let zero = IdentityCachedHash128::new_with_hasher(Number::new(0));
+        let one = IdentityCachedHash128::new_with_hasher(Number::new(1));
+        assert_eq!(CachedHash128::fingerprint(&zero), 0);
+        assert_eq!(CachedHash128::fingerprint(&one), 1);
+        assert_ne!(zero, one);
+        assert_eq!(zero.comparisons(), 0);
+        assert!(!CachedHash128::probably_eq(&zero, &one));
+    }
+
+    #[test]
+    fn eq_compares_values() {
+        let foo = IdentityCachedHash128::new_with_hasher(Number::new(2));
+        let bar = IdentityCachedHash128::new_with_hasher(Number::new(2));
+        assert_eq!(foo, bar);
+        assert_eq!(foo.comparisons(), 1);
+        assert!(CachedHash128::probably_eq(&foo, &bar));
+        assert_eq!(foo.comparisons(), 1);
+        assert_eq!(foo, bar);
+        assert_eq!(foo.comparisons(), 2);
+    }
+}
@@ -0,0 +1,166 @@
+use core::hash::BuildHasher;
+#[cfg(feature = "std")]
+use core::hash::BuildHasherDefault;
+#[cfg(feature = "std")]
+use std::collections::hash_map::DefaultHasher;
+
+use crate::cachedhash::wrapper;
+use crate::sentinel::{Fold32, SentinelPolicy};
+
+/// A compact version of [`CachedHash`](struct@crate::CachedHash) which caches only
+/// a 32-bit internal hash.
+///
+/// The 64-bit hash computed by `BH` is folded into 32 bits (by xoring its two
+/// halves) and stored in an [`AtomicU32`](core::sync::atomic::AtomicU32), with
+/// 0 reserved as the "not computed" sentinel the same way as in
+/// [`CachedHash`](struct@crate::CachedHash). This is done by the [`Fold32`]
+/// [`SentinelPolicy`], otherwise [`CachedHash32`] has the same API as
+/// [`CachedHash`](struct@crate::CachedHash). The [`Hash`](core::hash::Hash)
+/// implementation feeds the internal hash to the outer hasher using
+/// [`Hasher::write_u32`](core::hash::Hasher::write_u32) so identity hashers such
+/// as [`PassThroughHasher`](crate::PassThroughHasher) still work.
+///
+/// # Size
+///
+/// The saving is only real when the alignment of `T` is at most 4 bytes.
+/// For a `T` aligned to 8 bytes (for example one containing a pointer or
+/// a [`u64`]) the 4 bytes saved are lost to padding and [`CachedHash32`] is
+/// exactly as large as [`CachedHash`](struct@crate::CachedHash). Sizes on 64-bit targets:
+///
+/// | `T`         | `size_of::<T>()` | [`CachedHash`](struct@crate::CachedHash) | [`CachedHash32`] |
+/// |-------------|------------------|-----------------------------------|------------------|
+/// | `u32`       | 4                | 16                                | 8                |
+/// | `[u32; 4]`  | 16               | 24                                | 20               |
+/// | `[u8; 12]`  | 12               | 24                                | 16               |
+/// | `u64`       | 8                | 16                                | 16               |
+/// | `String`    | 24               | 32                                | 32               |
+///
+/// With only 32 bits the internal hash collides much more often, so equal
+/// cached hashes say less about equality of the values. Different cached hashes
+/// still prove the values differ and [`PartialEq`] uses that in the same way as
+/// [`CachedHash`](struct@crate::CachedHash) does.
+///
+/// Without the `std` feature `BH` has no default and needs to be always specified.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct CachedHash32<
+    T: ?Sized,
+    BH: BuildHasher = BuildHasherDefault<DefaultHasher>,
+    P: SentinelPolicy = Fold32,
+> {
+    hash: P::AtomicSlot,
+    build_hasher: BH,
+    value: T,
+}
+
+/// A compact version of [`CachedHash`](struct@crate::CachedHash) which caches only
+/// a 32-bit internal hash.
+///
+/// This is the `no_std` version of [`CachedHash32`] which requires the `BH`
+/// [`BuildHasher`] to be always specified. See the documentation of the crate
+/// built with the `std` feature for details.
+#[cfg(not(feature = "std"))]
+#[derive(Debug)]
+pub struct CachedHash32<T: ?Sized, BH: BuildHasher, P: SentinelPolicy = Fold32> {
+    hash: P::AtomicSlot,
+    build_hasher: BH,
+    value: T,
+}
+
+wrapper::impl_cached_hash!(
+    CachedHash32,
+    /// A guard giving mutable access to the value stored in a [`CachedHash32`].
+    ///
+    /// Created by [`CachedHash32::modify`] and [`CachedHash32::modify_with_policy`].
+    /// See [`CachedHashGuard`](crate::CachedHashGuard) for details.
+    CachedHash32Guard,
+    AtomicSlot,
+    Fold32,
+);
+
+#[cfg(test)]
+crate::cachedhash::wrapper::cached_hash_common_tests!(CachedHash32, cached_hash);
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::HashSet;
+    use std::hash::{BuildHasher, Hash, Hasher};
+    use std::mem::size_of;
+
+    use super::*;
+    use crate::{BuildPassThroughHasher, CachedHash};
+
+    fn calculate_hash<T: Hash>(t: &T) -> u64 {
+        let mut s = DefaultHasher::default();
+        t.hash(&mut s);
+        s.finish()
+    }
+
+    #[test]
+    fn internal_hash_is_folded() {
+        let foo = CachedHash32::new("foo".to_string());
+        let full = CachedHash::internal_hash(&CachedHash::new("foo".to_string())).get();
+        #[allow(clippy::cast_possible_truncation)]
+        let folded = (full ^ (full >> 32)) as u32;
+        assert_eq!(CachedHash32::internal_hash(&foo).get(), folded);
+    }
+
+    #[test]
+    fn writes_u32() {
+        let foo = CachedHash32::new("foo".to_string());
+        let mut hasher = DefaultHasher::default();
+        hasher.write_u32(CachedHash32::internal_hash(&foo).get());
+        assert_eq!(calculate_hash(&foo), hasher.finish());
+    }
+
+    #[test]
+    #[allow(clippy::mutable_key_type)]
+    fn works_with_pass_through_hasher() {
+        let set: HashSet<_, BuildPassThroughHasher> =
+            (0..100).map(|i| CachedHash32::new(i.to_string())).collect();
+        assert_eq!(set.len(), 100);
+        assert!(set.contains(&CachedHash32::new("42".to_string())));
+        let foo = CachedHash32::new("foo".to_string());
+        assert_eq!(
+            BuildPassThroughHasher::default().hash_one(&foo),
+            BuildPassThroughHasher::default().hash_one(&foo)
+        );
+    }
+
+    #[test]
+    fn modify_rehashes_folded() {
+        let mut foo = CachedHash32::new("foo".to_string());
+        CachedHash32::modify(&mut foo).push('d');
+        assert_eq!(
+            CachedHash32::cached_hash(&foo),
+            Some(CachedHash32::internal_hash(&CachedHash32::new(
+                "food".to_string()
+            )))
+        );
+        let (value, hash, build_hasher) = CachedHash32::into_parts(foo);
+        let foo = CachedHash32::from_parts(value, hash.unwrap(), build_hasher);
+        assert!(CachedHash32::verify_hash(&foo));
+    }
+
+    #[test]
+    fn struct_is_smaller() {
+        assert_eq!(size_of::<CachedHash32<u32>>(), 8);
+        assert!(size_of::<CachedHash32<u32>>() < size_of::<CachedHash<u32>>());
+        assert_eq!(size_of::<CachedHash32<[u32; 4]>>(), 20);
+        assert!(size_of::<CachedHash32<[u32; 4]>>() < size_of::<CachedHash<[u32; 4]>>());
+        assert_eq!(size_of::<CachedHash32<[u8; 12]>>(), 16);
+        assert!(size_of::<CachedHash32<[u8; 12]>>() < size_of::<CachedHash<[u8; 12]>>());
+        assert_eq!(size_of::<CachedHash32<(u16, u8)>>(), 8);
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn struct_is_not_smaller_for_8_byte_alignment() {
+        assert_eq!(size_of::<CachedHash32<u64>>(), size_of::<CachedHash<u64>>());
+        assert_eq!(
+            size_of::<CachedHash32<String>>(),
+            size_of::<CachedHash<String>>()
+        );
+    }
+}
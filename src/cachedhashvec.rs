@@ -0,0 +1,536 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
+use std::ops::{Bound, Deref, Range, RangeBounds};
+
+use crate::cachedhash::{cached_hashes_differ, compute_internal_hash};
+use crate::sentinel::{BumpZero, InternalHash, SentinelPolicy, Slot};
+
+/// The default number of elements per chunk of a [`CachedHashVec`].
+pub const DEFAULT_CHUNK_LEN: usize = 1024;
+
+/// A vector that caches the hash of every fixed-size chunk of its elements
+/// together with a root hash computed from them.
+///
+/// [`CachedHash<Vec<T>>`](struct@crate::CachedHash) has to rehash the whole
+/// vector after any modification. [`CachedHashVec`] splits the elements into
+/// chunks of `CHUNK_LEN` elements and caches the internal hash of each chunk
+/// separately. Modifying the vector through [`CachedHashVec::get_mut`],
+/// [`CachedHashVec::range_mut`], [`CachedHashVec::push`],
+/// [`CachedHashVec::pop`], [`CachedHashVec::truncate`] or [`Extend`]
+/// invalidates only the chunks that were touched and the root. Computing the
+/// root then rehashes only those chunks and combines the hashes of all chunks,
+/// which is much cheaper than rehashing all elements when the chunks are large.
+///
+/// The root is a function of the elements alone, not of the history of edits,
+/// so equal vectors hash the same and [`CachedHashVec`] can be used as a key in
+/// hash based collections just like [`CachedHash`](struct@crate::CachedHash).
+/// Its [`Hash`] implementation feeds the root into the hasher so it works well
+/// with [`PassThroughHasher`](crate::PassThroughHasher). The root depends on
+/// `CHUNK_LEN` which is why the chunk length is part of the type.
+///
+/// The hashes are stored atomically so [`CachedHashVec`] is [`Sync`] whenever
+/// `T` and `BH` are. Like with [`CachedHash`](struct@crate::CachedHash) the
+/// elements cannot use interior mutability in a way that affects their hash
+/// unless [`CachedHashVec::invalidate_hash`] is called afterwards.
+///
+/// Only available with the `std` feature.
+#[derive(Debug)]
+pub struct CachedHashVec<
+    T,
+    BH: BuildHasher = BuildHasherDefault<DefaultHasher>,
+    P: SentinelPolicy = BumpZero,
+    const CHUNK_LEN: usize = DEFAULT_CHUNK_LEN,
+> {
+    root: P::AtomicSlot,
+    chunk_hashes: Vec<P::AtomicSlot>,
+    build_hasher: BH,
+    values: Vec<T>,
+}
+
+impl<T> CachedHashVec<T> {
+    /// Creates a new [`CachedHashVec`] with the given elements using [`DefaultHasher`].
+    #[must_use]
+    pub fn new(values: Vec<T>) -> Self {
+        Self::new_with_hasher(values)
+    }
+}
+
+impl<T, H: Hasher + Default, const CHUNK_LEN: usize>
+    CachedHashVec<T, BuildHasherDefault<H>, BumpZero, CHUNK_LEN>
+{
+    /// Creates a new [`CachedHashVec`] with the given elements using a provided
+    /// hasher type implementing [`Default`].
+    #[must_use]
+    pub fn new_with_hasher(values: Vec<T>) -> Self {
+        Self::new_with_build_hasher(values, BuildHasherDefault::default())
+    }
+}
+
+impl<T, BH: BuildHasher, const CHUNK_LEN: usize> CachedHashVec<T, BH, BumpZero, CHUNK_LEN> {
+    /// Creates a new [`CachedHashVec`] with the given elements and [`BuildHasher`].
+    pub fn new_with_build_hasher(values: Vec<T>, build_hasher: BH) -> Self {
+        Self::new_with_sentinel(values, build_hasher, BumpZero)
+    }
+}
+
+impl<T, BH: BuildHasher, P: SentinelPolicy, const CHUNK_LEN: usize>
+    CachedHashVec<T, BH, P, CHUNK_LEN>
+{
+    /// Creates a new [`CachedHashVec`] with the given elements and [`BuildHasher`]
+    /// using the given [`SentinelPolicy`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `CHUNK_LEN` is zero.
+    pub fn new_with_sentinel(values: Vec<T>, build_hasher: BH, _sentinel: P) -> Self {
+        assert!(CHUNK_LEN > 0, "the chunk length must not be zero");
+        let chunk_hashes = (0..values.len().div_ceil(CHUNK_LEN))
+            .map(|_| <P::AtomicSlot as Slot<P::Hash>>::NONE)
+            .collect();
+        Self {
+            root: <P::AtomicSlot as Slot<P::Hash>>::NONE,
+            chunk_hashes,
+            build_hasher,
+            values,
+        }
+    }
+
+    /// Explicitly returns an immutable reference to the elements.
+    #[inline]
+    #[must_use]
+    pub const fn get(this: &Self) -> &[T] {
+        this.values.as_slice()
+    }
+
+    /// Returns a mutable reference to the element at `index`, or [`None`] if
+    /// it is out of bounds, and invalidates the hash of its chunk.
+    #[inline]
+    #[must_use]
+    pub fn get_mut(this: &mut Self, index: usize) -> Option<&mut T> {
+        if index < this.values.len() {
+            this.invalidate_chunks(index..index + 1);
+        }
+        this.values.get_mut(index)
+    }
+
+    /// Returns a mutable reference to the elements in `range` and invalidates
+    /// the hashes of the chunks they belong to.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is out of bounds, like indexing a slice.
+    #[must_use]
+    pub fn range_mut(this: &mut Self, range: impl RangeBounds<usize>) -> &mut [T] {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end.saturating_add(1),
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => this.values.len(),
+        };
+        // check the bounds before invalidating anything
+        let _ = &this.values[start..end];
+        this.invalidate_chunks(start..end);
+        &mut this.values[start..end]
+    }
+
+    /// Appends an element, invalidating the hash of the last chunk.
+    pub fn push(this: &mut Self, value: T) {
+        let len = this.values.len();
+        this.values.push(value);
+        this.resize_chunks();
+        this.invalidate_chunks(len..len + 1);
+    }
+
+    /// Removes the last element and returns it, or [`None`] if the vector is
+    /// empty. Invalidates the hash of the last chunk.
+    pub fn pop(this: &mut Self) -> Option<T> {
+        let value = this.values.pop()?;
+        let len = this.values.len();
+        this.resize_chunks();
+        this.invalidate_chunks(len..len + 1);
+        Some(value)
+    }
+
+    /// Shortens the vector to `len` elements, dropping the rest. Invalidates
+    /// the hash of the new last chunk if it was cut. Does nothing if the
+    /// vector is not longer than `len`.
+    pub fn truncate(this: &mut Self, len: usize) {
+        if len >= this.values.len() {
+            return;
+        }
+        this.values.truncate(len);
+        this.resize_chunks();
+        this.invalidate_chunks(len..len + 1);
+    }
+
+    /// Gives mutable access to the whole [`Vec`] through `f` and invalidates
+    /// all the hashes afterwards.
+    pub fn modify<R>(this: &mut Self, f: impl FnOnce(&mut Vec<T>) -> R) -> R {
+        let result = f(&mut this.values);
+        this.resize_chunks();
+        Self::invalidate_hash(this);
+        result
+    }
+
+    /// Explicitly invalidates the hashes of all the chunks and the root.
+    ///
+    /// This is only necessary if the elements use interior mutability in
+    /// a way that affects their hash.
+    pub fn invalidate_hash(this: &mut Self) {
+        for hash in &this.chunk_hashes {
+            hash.set(None);
+        }
+        this.root.set(None);
+    }
+
+    /// Returns the cached root hash if it is currently cached.
+    #[inline]
+    #[must_use]
+    pub fn cached_hash(this: &Self) -> Option<P::Hash> {
+        this.root.get()
+    }
+
+    /// Returns the root hash, computing the hashes of the chunks that were
+    /// invalidated and the root if necessary.
+    ///
+    /// The root is computed by `BH` from the number of elements and the
+    /// internal hashes of all the chunks in order.
+    #[inline]
+    #[must_use]
+    pub fn internal_hash(this: &Self) -> P::Hash
+    where
+        T: Hash,
+    {
+        this.root.get().unwrap_or_else(|| this.store_root())
+    }
+
+    /// Returns the number of chunks, the last one can be shorter than `CHUNK_LEN`.
+    #[inline]
+    #[must_use]
+    pub const fn chunk_count(this: &Self) -> usize {
+        this.chunk_hashes.len()
+    }
+
+    /// Destructs the [`CachedHashVec`] and returns the elements.
+    #[inline]
+    #[must_use]
+    pub fn take_value(this: Self) -> Vec<T> {
+        this.values
+    }
+
+    /// Computes the hashes of the invalidated chunks and the root and caches them.
+    fn store_root(&self) -> P::Hash
+    where
+        T: Hash,
+    {
+        let mut hasher = self.build_hasher.build_hasher();
+        hasher.write_u64(self.values.len() as u64);
+        for (chunk, hash) in self.values.chunks(CHUNK_LEN).zip(&self.chunk_hashes) {
+            let chunk_hash = hash.get().unwrap_or_else(|| {
+                let chunk_hash = compute_internal_hash::<_, _, P>(chunk, &self.build_hasher);
+                hash.set(Some(chunk_hash));
+                chunk_hash
+            });
+            hasher.write_u64(chunk_hash.to_u64());
+        }
+        let root = P::from_raw(hasher.finish());
+        self.root.set(Some(root));
+        root
+    }
+
+    /// Invalidates the root and the hashes of the chunks containing the
+    /// elements in `range`.
+    fn invalidate_chunks(&self, range: Range<usize>) {
+        self.root.set(None);
+        if range.is_empty() {
+            return;
+        }
+        let last = (range.end - 1) / CHUNK_LEN;
+        for hash in self
+            .chunk_hashes
+            .iter()
+            .take(last + 1)
+            .skip(range.start / CHUNK_LEN)
+        {
+            hash.set(None);
+        }
+    }
+
+    /// Adds or removes chunk hashes to match the number of elements.
+    fn resize_chunks(&mut self) {
+        let chunks = self.values.len().div_ceil(CHUNK_LEN);
+        self.chunk_hashes
+            .resize_with(chunks, || <P::AtomicSlot as Slot<P::Hash>>::NONE);
+    }
+}
+
+impl<T: Clone, BH: BuildHasher + Clone, P: SentinelPolicy, const CHUNK_LEN: usize> Clone
+    for CachedHashVec<T, BH, P, CHUNK_LEN>
+{
+    fn clone(&self) -> Self {
+        Self {
+            root: self.root.clone(),
+            chunk_hashes: self.chunk_hashes.clone(),
+            build_hasher: self.build_hasher.clone(),
+            values: self.values.clone(),
+        }
+    }
+}
+
+/// See the `Equality` section of [`CachedHash`](struct@crate::CachedHash)
+/// for when the elements are not compared.
+impl<T: PartialEq, BH: BuildHasher, P: SentinelPolicy, const CHUNK_LEN: usize> PartialEq
+    for CachedHashVec<T, BH, P, CHUNK_LEN>
+{
+    fn eq(&self, other: &Self) -> bool {
+        if cached_hashes_differ::<BH, _>(self.root.get(), other.root.get()) {
+            return false;
+        }
+        self.values == other.values
+    }
+}
+
+impl<T: Eq, BH: BuildHasher, P: SentinelPolicy, const CHUNK_LEN: usize> Eq
+    for CachedHashVec<T, BH, P, CHUNK_LEN>
+{
+}
+
+impl<T: Hash, BH: BuildHasher, P: SentinelPolicy, const CHUNK_LEN: usize> Hash
+    for CachedHashVec<T, BH, P, CHUNK_LEN>
+{
+    fn hash<H2: Hasher>(&self, state: &mut H2) {
+        Self::internal_hash(self).write_to(state);
+    }
+}
+
+impl<T, BH: BuildHasher, P: SentinelPolicy, const CHUNK_LEN: usize> Deref
+    for CachedHashVec<T, BH, P, CHUNK_LEN>
+{
+    type Target = [T];
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        Self::get(self)
+    }
+}
+
+impl<T, BH: BuildHasher, P: SentinelPolicy, const CHUNK_LEN: usize> AsRef<[T]>
+    for CachedHashVec<T, BH, P, CHUNK_LEN>
+{
+    fn as_ref(&self) -> &[T] {
+        Self::get(self)
+    }
+}
+
+impl<T, BH: BuildHasher + Default, P: SentinelPolicy, const CHUNK_LEN: usize> Default
+    for CachedHashVec<T, BH, P, CHUNK_LEN>
+{
+    fn default() -> Self {
+        Self::new_with_sentinel(Vec::new(), BH::default(), P::default())
+    }
+}
+
+impl<T, H: Hasher + Default, P: SentinelPolicy, const CHUNK_LEN: usize> From<Vec<T>>
+    for CachedHashVec<T, BuildHasherDefault<H>, P, CHUNK_LEN>
+{
+    fn from(values: Vec<T>) -> Self {
+        Self::new_with_sentinel(values, BuildHasherDefault::default(), P::default())
+    }
+}
+
+impl<T, BH: BuildHasher, P: SentinelPolicy, const CHUNK_LEN: usize> Extend<T>
+    for CachedHashVec<T, BH, P, CHUNK_LEN>
+{
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let len = self.values.len();
+        self.values.extend(iter);
+        self.resize_chunks();
+        self.invalidate_chunks(len..self.values.len());
+    }
+}
+
+impl<T, BH: BuildHasher + Default, P: SentinelPolicy, const CHUNK_LEN: usize> FromIterator<T>
+    for CachedHashVec<T, BH, P, CHUNK_LEN>
+{
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self::new_with_sentinel(iter.into_iter().collect(), BH::default(), P::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::HashSet;
+    use std::hash::{Hash, Hasher};
+
+    use oorandom::Rand64;
+
+    use super::*;
+    use crate::{BuildPassThroughHasher, NoSentinel};
+
+    type SmallChunks<T> = CachedHashVec<T, BuildHasherDefault<DefaultHasher>, BumpZero, 4>;
+
+    fn calculate_hash<T: Hash>(t: &T) -> u64 {
+        let mut s = DefaultHasher::default();
+        t.hash(&mut s);
+        s.finish()
+    }
+
+    fn fresh<T: Clone>(vec: &SmallChunks<T>) -> SmallChunks<T> {
+        SmallChunks::from(CachedHashVec::get(vec).to_vec())
+    }
+
+    fn cached_chunks<T>(vec: &SmallChunks<T>) -> Vec<bool> {
+        vec.chunk_hashes
+            .iter()
+            .map(|hash| hash.get().is_some())
+            .collect()
+    }
+
+    #[test]
+    fn edits_invalidate_only_their_chunk() {
+        let mut vec = SmallChunks::from((0..10).collect::<Vec<u32>>());
+        assert_eq!(CachedHashVec::chunk_count(&vec), 3);
+        let _ = CachedHashVec::internal_hash(&vec);
+        assert_eq!(cached_chunks(&vec), [true, true, true]);
+
+        *CachedHashVec::get_mut(&mut vec, 5).unwrap() = 50;
+        assert_eq!(CachedHashVec::cached_hash(&vec), None);
+        assert_eq!(cached_chunks(&vec), [true, false, true]);
+        assert_eq!(
+            CachedHashVec::internal_hash(&vec),
+            CachedHashVec::internal_hash(&fresh(&vec))
+        );
+
+        CachedHashVec::range_mut(&mut vec, 3..=4).fill(0);
+        assert_eq!(cached_chunks(&vec), [false, false, true]);
+        let _ = CachedHashVec::internal_hash(&vec);
+
+        assert!(CachedHashVec::get_mut(&mut vec, 10).is_none());
+        assert_eq!(cached_chunks(&vec), [true, true, true]);
+        CachedHashVec::range_mut(&mut vec, 4..4).fill(1);
+        assert_eq!(cached_chunks(&vec), [true, true, true]);
+    }
+
+    #[test]
+    fn push_pop_truncate() {
+        let mut vec = SmallChunks::from(vec![1_u8, 2, 3, 4]);
+        let _ = CachedHashVec::internal_hash(&vec);
+        CachedHashVec::push(&mut vec, 5);
+        assert_eq!(cached_chunks(&vec), [true, false]);
+        let _ = CachedHashVec::internal_hash(&vec);
+        assert_eq!(CachedHashVec::pop(&mut vec), Some(5));
+        assert_eq!(cached_chunks(&vec), [true]);
+        assert_eq!(
+            CachedHashVec::internal_hash(&vec),
+            CachedHashVec::internal_hash(&fresh(&vec))
+        );
+        vec.extend([5, 6, 7, 8, 9]);
+        let _ = CachedHashVec::internal_hash(&vec);
+        CachedHashVec::truncate(&mut vec, 6);
+        assert_eq!(cached_chunks(&vec), [true, false]);
+        CachedHashVec::truncate(&mut vec, 10);
+        assert_eq!(*vec, [1, 2, 3, 4, 5, 6]);
+        CachedHashVec::truncate(&mut vec, 0);
+        assert_eq!(CachedHashVec::chunk_count(&vec), 0);
+        assert_eq!(CachedHashVec::pop(&mut vec), None);
+        assert_eq!(
+            CachedHashVec::internal_hash(&vec),
+            CachedHashVec::internal_hash(&SmallChunks::<u8>::default())
+        );
+    }
+
+    #[test]
+    fn root_is_independent_of_history() {
+        let mut rng = Rand64::new(7);
+        let mut vec = SmallChunks::from(vec![0_u64; 50]);
+        for _ in 0..2000 {
+            let index = rng.rand_range(0..60);
+            #[allow(clippy::cast_possible_truncation)]
+            let index = index as usize;
+            match rng.rand_range(0..6) {
+                0 | 1 => {
+                    if let Some(value) = CachedHashVec::get_mut(&mut vec, index) {
+                        *value = rng.rand_u64() % 4;
+                    }
+                }
+                2 => CachedHashVec::push(&mut vec, rng.rand_u64() % 4),
+                3 => {
+                    CachedHashVec::pop(&mut vec);
+                }
+                4 => CachedHashVec::truncate(&mut vec, index.max(40)),
+                _ => {
+                    let start = index.min(vec.len());
+                    let end = (start + 5).min(vec.len());
+                    CachedHashVec::range_mut(&mut vec, start..end).reverse();
+                }
+            }
+            if rng.rand_range(0..2) == 0 {
+                assert_eq!(
+                    CachedHashVec::internal_hash(&vec),
+                    CachedHashVec::internal_hash(&fresh(&vec))
+                );
+                assert_eq!(vec, fresh(&vec));
+            }
+        }
+    }
+
+    #[test]
+    fn equal_vectors_hash_the_same() {
+        let foo = CachedHashVec::new(vec![1, 2, 3]);
+        let mut bar = CachedHashVec::new(vec![1, 2]);
+        let _ = CachedHashVec::internal_hash(&bar);
+        CachedHashVec::push(&mut bar, 3);
+        assert_eq!(foo, bar);
+        assert_eq!(calculate_hash(&foo), calculate_hash(&bar));
+        assert_ne!(foo, CachedHashVec::new(vec![1, 2, 4]));
+        assert_ne!(
+            CachedHashVec::internal_hash(&CachedHashVec::new(vec![0])),
+            CachedHashVec::internal_hash(&CachedHashVec::new(vec![0, 0]))
+        );
+    }
+
+    #[test]
+    #[allow(clippy::mutable_key_type)]
+    fn in_hash_set() {
+        let mut set = HashSet::<_, BuildPassThroughHasher>::default();
+        for i in 0..100_u8 {
+            set.insert(CachedHashVec::new(vec![i; 3000]));
+        }
+        let mut key = CachedHashVec::new(vec![42; 3000]);
+        assert!(set.contains(&key));
+        *CachedHashVec::get_mut(&mut key, 2999).unwrap() = 0;
+        assert!(!set.contains(&key));
+    }
+
+    #[test]
+    fn with_sentinel() {
+        let vec = CachedHashVec::<_, _, _, 2>::new_with_sentinel(
+            vec![1, 2, 3],
+            BuildHasherDefault::<DefaultHasher>::default(),
+            NoSentinel,
+        );
+        assert_eq!(CachedHashVec::chunk_count(&vec), 2);
+        let clone = vec.clone();
+        assert_eq!(
+            CachedHashVec::internal_hash(&vec),
+            CachedHashVec::internal_hash(&clone)
+        );
+        assert_eq!(
+            CachedHashVec::take_value(clone)
+                .into_iter()
+                .collect::<CachedHashVec<_, BuildHasherDefault<DefaultHasher>, NoSentinel, 2>>(),
+            vec
+        );
+    }
+
+    #[test]
+    fn is_send_and_sync() {
+        const fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<CachedHashVec<u8>>();
+    }
+}
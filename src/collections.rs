@@ -0,0 +1,579 @@
+use std::collections::hash_map::{self, DefaultHasher, Entry};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use std::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
+use std::num::NonZeroU64;
+
+use crate::cachedhash::compute_internal_hash;
+use crate::sentinel::BumpZero;
+use crate::{BuildPassThroughHasher, CachedHash};
+
+/// A [`HashSet`] of [`CachedHash`](struct@CachedHash) values that uses the cached internal hash
+/// directly via [`PassThroughHasher`](crate::PassThroughHasher).
+pub type CachedHashSet<T, BH = BuildHasherDefault<DefaultHasher>> =
+    HashSet<CachedHash<T, BH>, BuildPassThroughHasher>;
+
+/// A hash map with [`CachedHash`](struct@CachedHash) keys that can be queried by references to
+/// the unwrapped keys.
+///
+/// The [`Hash`] implementation of [`CachedHash<K>`](struct@CachedHash) differs from
+/// the one of `K` so looking up `&K` in a `HashMap<CachedHash<K>, V>` via the
+/// [`Borrow<K>`](std::borrow::Borrow) implementation of [`CachedHash`](struct@CachedHash) silently
+/// fails to find the entry. [`CachedHashMap`] instead computes the internal hash
+/// of the queried `&K` using its own `BH` [`BuildHasher`] so that [`CachedHashMap::get`],
+/// [`CachedHashMap::get_mut`], [`CachedHashMap::contains_key`] and [`CachedHashMap::remove`]
+/// work with plain references.
+///
+/// Keys are stored in a [`HashMap`] using [`PassThroughHasher`](crate::PassThroughHasher)
+/// so the cached internal hash is used as is and moving keys between maps does
+/// no hashing work once it is cached.
+///
+/// The keys must compute their internal hash in the same way as the `BH` stored
+/// in the map. This is always the case for zero-sized [`BuildHasher`]s such as
+/// the default [`BuildHasherDefault`]. For a [`BuildHasher`] with state, such as
+/// [`RandomState`](std::collections::hash_map::RandomState), create the keys
+/// with clones of [`CachedHashMap::build_hasher`].
+pub struct CachedHashMap<K: Eq + Hash, V, BH: BuildHasher = BuildHasherDefault<DefaultHasher>> {
+    map: HashMap<CachedHash<K, BH>, V, BuildPassThroughHasher>,
+    build_hasher: BH,
+}
+
+/// A key that can be looked up in a [`CachedHashMap`].
+///
+/// This is an implementation detail that lets [`CachedHashMap`] look up
+/// both [`CachedHash`](struct@CachedHash) keys and plain references using the same internal hash.
+/// It only shows up in [`Borrow`](std::borrow::Borrow) implementations, is
+/// sealed and cannot be implemented outside of this crate.
+pub trait KeyQuery<K>: sealed::Sealed {
+    /// Returns the queried value.
+    fn value(&self) -> &K;
+
+    /// Writes the internal hash of the value into `state` in the same way the
+    /// [`Hash`] implementation of [`CachedHash`](struct@CachedHash) does.
+    fn hash_internal(&self, state: &mut dyn Hasher);
+}
+
+impl<K: Eq> PartialEq for dyn KeyQuery<K> + '_ {
+    fn eq(&self, other: &Self) -> bool {
+        self.value() == other.value()
+    }
+}
+
+impl<K: Eq> Eq for dyn KeyQuery<K> + '_ {}
+
+impl<K> Hash for dyn KeyQuery<K> + '_ {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.hash_internal(state);
+    }
+}
+
+/// A plain reference to a key together with its internal hash.
+pub struct Lookup<'a, K> {
+    value: &'a K,
+    hash: NonZeroU64,
+}
+
+impl<'a, K: Hash> Lookup<'a, K> {
+    /// Creates a query for `value` hashed by `build_hasher`.
+    pub fn new<BH: BuildHasher>(value: &'a K, build_hasher: &BH) -> Self {
+        Self::prehashed(
+            value,
+            compute_internal_hash::<_, _, BumpZero>(value, build_hasher),
+        )
+    }
+
+    /// Creates a query for `value` whose internal hash is already known.
+    pub const fn prehashed(value: &'a K, hash: NonZeroU64) -> Self {
+        Self { value, hash }
+    }
+}
+
+impl<K> KeyQuery<K> for Lookup<'_, K> {
+    fn value(&self) -> &K {
+        self.value
+    }
+
+    fn hash_internal(&self, state: &mut dyn Hasher) {
+        state.write_u64(self.hash.get());
+    }
+}
+
+impl<K> sealed::Sealed for Lookup<'_, K> {}
+
+pub mod sealed {
+    /// Keeps [`KeyQuery`](super::KeyQuery) from being implemented outside of this crate.
+    pub trait Sealed {}
+}
+
+impl<K: Eq + Hash, V> CachedHashMap<K, V> {
+    /// Creates an empty [`CachedHashMap`] whose keys use [`DefaultHasher`].
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty [`CachedHashMap`] with at least the specified capacity
+    /// whose keys use [`DefaultHasher`].
+    #[must_use]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_build_hasher(capacity, BuildHasherDefault::default())
+    }
+}
+
+impl<K: Eq + Hash, V, BH: BuildHasher> CachedHashMap<K, V, BH> {
+    /// Creates an empty [`CachedHashMap`] which computes internal hashes of
+    /// queried keys using `build_hasher`.
+    pub fn with_build_hasher(build_hasher: BH) -> Self {
+        Self {
+            map: HashMap::default(),
+            build_hasher,
+        }
+    }
+
+    /// Creates an empty [`CachedHashMap`] with at least the specified capacity
+    /// which computes internal hashes of queried keys using `build_hasher`.
+    pub fn with_capacity_and_build_hasher(capacity: usize, build_hasher: BH) -> Self {
+        Self {
+            map: HashMap::with_capacity_and_hasher(capacity, BuildPassThroughHasher::default()),
+            build_hasher,
+        }
+    }
+
+    /// Returns a reference to the [`BuildHasher`] used to compute internal
+    /// hashes of queried keys.
+    #[inline]
+    pub const fn build_hasher(&self) -> &BH {
+        &self.build_hasher
+    }
+
+    /// Returns the number of elements in the map.
+    #[inline]
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the map contains no elements.
+    #[inline]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns the number of elements the map can hold without reallocating.
+    #[inline]
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.map.capacity()
+    }
+
+    /// Reserves capacity for at least `additional` more elements.
+    #[inline]
+    pub fn reserve(&mut self, additional: usize) {
+        self.map.reserve(additional);
+    }
+
+    /// Clears the map, removing all key-value pairs.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+
+    /// Inserts a key-value pair into the map. If the map already had the key
+    /// present, the value is updated and the old value is returned.
+    #[inline]
+    pub fn insert(&mut self, key: CachedHash<K, BH>, value: V) -> Option<V> {
+        self.map.insert(key, value)
+    }
+
+    /// Gets the given key's corresponding entry in the map for in-place manipulation.
+    ///
+    /// See [`CachedHashMap::entry_ref`] to avoid creating a key that is already present.
+    #[inline]
+    pub fn entry(&mut self, key: CachedHash<K, BH>) -> Entry<'_, CachedHash<K, BH>, V> {
+        self.map.entry(key)
+    }
+
+    /// Gets the entry of a plain key reference for in-place manipulation.
+    ///
+    /// The internal hash of `key` is computed once using the map's `BH`. The
+    /// key is only cloned (together with `BH`) when a new entry is inserted,
+    /// in which case the computed hash is cached in the new [`CachedHash`](struct@CachedHash).
+    ///
+    /// Unlike [`HashMap::entry`] this probes the table twice: once here to find
+    /// out whether `key` is present and once more when the value is accessed
+    /// or inserted through the returned [`EntryRef`]. [`HashMap`] has no stable
+    /// API keeping the position of a key looked up by reference. The second
+    /// probe reuses the computed hash but compares `key` with the stored keys
+    /// again, so prefer [`CachedHashMap::entry`] when an owned key is at hand
+    /// and comparing keys is expensive.
+    pub fn entry_ref<'a, 'q>(&'a mut self, key: &'q K) -> EntryRef<'a, 'q, K, V, BH> {
+        let lookup = self.lookup(key);
+        let occupied = self.map.contains_key(&lookup as &dyn KeyQuery<K>);
+        EntryRef {
+            hash: lookup.hash,
+            map: self,
+            key,
+            occupied,
+        }
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    #[inline]
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.map.get(&self.lookup(key) as &dyn KeyQuery<K>)
+    }
+
+    /// Returns the key-value pair corresponding to the supplied key.
+    #[inline]
+    pub fn get_key_value(&self, key: &K) -> Option<(&CachedHash<K, BH>, &V)> {
+        self.map
+            .get_key_value(&self.lookup(key) as &dyn KeyQuery<K>)
+    }
+
+    /// Returns a mutable reference to the value corresponding to the key.
+    #[inline]
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let lookup = self.lookup(key);
+        self.map.get_mut(&lookup as &dyn KeyQuery<K>)
+    }
+
+    /// Returns `true` if the map contains a value for the specified key.
+    #[inline]
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.map.contains_key(&self.lookup(key) as &dyn KeyQuery<K>)
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key
+    /// was previously in the map.
+    #[inline]
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.remove_entry(key).map(|(_, value)| value)
+    }
+
+    /// Removes a key from the map, returning the stored key and value if the
+    /// key was previously in the map.
+    #[inline]
+    pub fn remove_entry(&mut self, key: &K) -> Option<(CachedHash<K, BH>, V)> {
+        let lookup = self.lookup(key);
+        self.map.remove_entry(&lookup as &dyn KeyQuery<K>)
+    }
+
+    /// An iterator visiting all key-value pairs in arbitrary order.
+    #[inline]
+    pub fn iter(&self) -> hash_map::Iter<'_, CachedHash<K, BH>, V> {
+        self.map.iter()
+    }
+
+    /// An iterator visiting all key-value pairs in arbitrary order, with
+    /// mutable references to the values.
+    #[inline]
+    pub fn iter_mut(&mut self) -> hash_map::IterMut<'_, CachedHash<K, BH>, V> {
+        self.map.iter_mut()
+    }
+
+    /// An iterator visiting all keys in arbitrary order.
+    #[inline]
+    pub fn keys(&self) -> hash_map::Keys<'_, CachedHash<K, BH>, V> {
+        self.map.keys()
+    }
+
+    /// An iterator visiting all values in arbitrary order.
+    #[inline]
+    pub fn values(&self) -> hash_map::Values<'_, CachedHash<K, BH>, V> {
+        self.map.values()
+    }
+
+    /// An iterator visiting all values mutably in arbitrary order.
+    #[inline]
+    pub fn values_mut(&mut self) -> hash_map::ValuesMut<'_, CachedHash<K, BH>, V> {
+        self.map.values_mut()
+    }
+
+    /// Clears the map, returning all key-value pairs as an iterator.
+    #[inline]
+    pub fn drain(&mut self) -> hash_map::Drain<'_, CachedHash<K, BH>, V> {
+        self.map.drain()
+    }
+
+    /// Returns a reference to the underlying [`HashMap`].
+    #[inline]
+    #[allow(clippy::mutable_key_type)] // the interior mutability only caches the hash
+    pub const fn as_hash_map(&self) -> &HashMap<CachedHash<K, BH>, V, BuildPassThroughHasher> {
+        &self.map
+    }
+
+    /// Destructs the [`CachedHashMap`] and returns the underlying [`HashMap`].
+    #[inline]
+    #[must_use]
+    #[allow(clippy::missing_const_for_fn)] // false positive, `build_hasher` might get dropped
+    #[allow(clippy::mutable_key_type)] // the interior mutability only caches the hash
+    pub fn into_hash_map(self) -> HashMap<CachedHash<K, BH>, V, BuildPassThroughHasher> {
+        self.map
+    }
+
+    #[inline]
+    fn lookup<'a>(&self, key: &'a K) -> Lookup<'a, K> {
+        Lookup::new(key, &self.build_hasher)
+    }
+}
+
+/// An entry of a [`CachedHashMap`] queried by a plain key reference.
+///
+/// Created by [`CachedHashMap::entry_ref`]. It only remembers whether the key
+/// is present, not where, so accessing or inserting the value probes the map
+/// again.
+pub struct EntryRef<'a, 'q, K: Eq + Hash, V, BH: BuildHasher> {
+    map: &'a mut CachedHashMap<K, V, BH>,
+    key: &'q K,
+    hash: NonZeroU64,
+    occupied: bool,
+}
+
+impl<'a, K: Eq + Hash, V, BH: BuildHasher> EntryRef<'a, '_, K, V, BH> {
+    /// Returns the queried key.
+    #[inline]
+    #[must_use]
+    pub const fn key(&self) -> &K {
+        self.key
+    }
+
+    /// Returns `true` if the map contains the queried key.
+    #[inline]
+    #[must_use]
+    pub const fn is_occupied(&self) -> bool {
+        self.occupied
+    }
+
+    /// Ensures a value is in the entry by inserting `default` if empty, and
+    /// returns a mutable reference to the value.
+    #[inline]
+    pub fn or_insert(self, default: V) -> &'a mut V
+    where
+        K: Clone,
+        BH: Clone,
+    {
+        self.or_insert_with(|| default)
+    }
+
+    /// Ensures a value is in the entry by inserting the result of `default`
+    /// if empty, and returns a mutable reference to the value.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V
+    where
+        K: Clone,
+        BH: Clone,
+    {
+        if self.occupied {
+            return self.into_occupied_value();
+        }
+        let key =
+            CachedHash::from_parts(self.key.clone(), self.hash, self.map.build_hasher.clone());
+        self.map.map.entry(key).or_insert_with(default)
+    }
+
+    /// Ensures a value is in the entry by inserting the default value if
+    /// empty, and returns a mutable reference to the value.
+    #[inline]
+    pub fn or_default(self) -> &'a mut V
+    where
+        K: Clone,
+        BH: Clone,
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    /// Modifies the value of an occupied entry using `f`.
+    #[inline]
+    #[must_use]
+    pub fn and_modify<F: FnOnce(&mut V)>(mut self, f: F) -> Self {
+        if self.occupied {
+            f(self.occupied_value());
+        }
+        self
+    }
+
+    fn occupied_value(&mut self) -> &mut V {
+        let lookup = Lookup::prehashed(self.key, self.hash);
+        self.map
+            .map
+            .get_mut(&lookup as &dyn KeyQuery<K>)
+            .unwrap_or_else(|| unreachable!("the map cannot change while borrowed by the entry"))
+    }
+
+    fn into_occupied_value(self) -> &'a mut V {
+        let lookup = Lookup::prehashed(self.key, self.hash);
+        self.map
+            .map
+            .get_mut(&lookup as &dyn KeyQuery<K>)
+            .unwrap_or_else(|| unreachable!("the map cannot change while borrowed by the entry"))
+    }
+}
+
+impl<K: Eq + Hash + Debug, V: Debug, BH: BuildHasher> Debug for EntryRef<'_, '_, K, V, BH> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EntryRef")
+            .field("key", self.key)
+            .field("occupied", &self.occupied)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<K: Eq + Hash, V, BH: BuildHasher + Default> Default for CachedHashMap<K, V, BH> {
+    fn default() -> Self {
+        Self::with_build_hasher(BH::default())
+    }
+}
+
+impl<K: Eq + Hash + Debug, V: Debug, BH: BuildHasher + Debug> Debug for CachedHashMap<K, V, BH> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.map.iter()).finish()
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone, BH: BuildHasher + Clone> Clone for CachedHashMap<K, V, BH> {
+    fn clone(&self) -> Self {
+        Self {
+            map: self.map.clone(),
+            build_hasher: self.build_hasher.clone(),
+        }
+    }
+}
+
+impl<K: Eq + Hash, V, BH: BuildHasher> Extend<(CachedHash<K, BH>, V)> for CachedHashMap<K, V, BH> {
+    fn extend<I: IntoIterator<Item = (CachedHash<K, BH>, V)>>(&mut self, iter: I) {
+        self.map.extend(iter);
+    }
+}
+
+impl<K: Eq + Hash, V, BH: BuildHasher + Default> FromIterator<(CachedHash<K, BH>, V)>
+    for CachedHashMap<K, V, BH>
+{
+    fn from_iter<I: IntoIterator<Item = (CachedHash<K, BH>, V)>>(iter: I) -> Self {
+        let mut map = Self::default();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K: Eq + Hash, V, BH: BuildHasher> IntoIterator for CachedHashMap<K, V, BH> {
+    type Item = (CachedHash<K, BH>, V);
+    type IntoIter = hash_map::IntoIter<CachedHash<K, BH>, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.map.into_iter()
+    }
+}
+
+impl<'a, K: Eq + Hash, V, BH: BuildHasher> IntoIterator for &'a CachedHashMap<K, V, BH> {
+    type Item = (&'a CachedHash<K, BH>, &'a V);
+    type IntoIter = hash_map::Iter<'a, CachedHash<K, BH>, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.map.iter()
+    }
+}
+
+impl<'a, K: Eq + Hash, V, BH: BuildHasher> IntoIterator for &'a mut CachedHashMap<K, V, BH> {
+    type Item = (&'a CachedHash<K, BH>, &'a mut V);
+    type IntoIter = hash_map::IterMut<'a, CachedHash<K, BH>, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.map.iter_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::RandomState;
+
+    use super::*;
+
+    #[test]
+    fn lookup_by_reference() {
+        let mut map = CachedHashMap::new();
+        map.insert(CachedHash::new("foo".to_string()), 1);
+        map.insert(CachedHash::new("bar".to_string()), 2);
+        assert_eq!(map.get(&"foo".to_string()), Some(&1));
+        assert_eq!(map.get(&"baz".to_string()), None);
+        assert!(map.contains_key(&"bar".to_string()));
+        *map.get_mut(&"bar".to_string()).unwrap() += 1;
+        assert_eq!(map.remove(&"bar".to_string()), Some(3));
+        assert!(!map.contains_key(&"bar".to_string()));
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn lookup_by_cached_hash_and_reference_agree() {
+        let mut map = CachedHashMap::new();
+        let key = CachedHash::new("foo".to_string());
+        *map.entry(key.clone()).or_insert(0) += 1;
+        *map.entry(key).or_insert(0) += 1;
+        assert_eq!(map.get(&"foo".to_string()), Some(&2));
+        assert_eq!(
+            map.as_hash_map().get(&CachedHash::new("foo".to_string())),
+            Some(&2)
+        );
+    }
+
+    #[test]
+    fn entry_ref_clones_only_on_insert() {
+        use std::rc::Rc;
+
+        let mut map = CachedHashMap::new();
+        let key = Rc::new("foo".to_string());
+        *map.entry_ref(&key).or_insert(0) += 1;
+        assert_eq!(Rc::strong_count(&key), 2);
+        let entry = map.entry_ref(&key);
+        assert!(entry.is_occupied());
+        *entry.and_modify(|value| *value += 1).or_default() += 1;
+        assert_eq!(Rc::strong_count(&key), 2);
+        assert_eq!(map.get(&key), Some(&3));
+        let (stored, _) = map.get_key_value(&key).unwrap();
+        assert!(CachedHash::cached_hash(stored).is_some());
+        assert!(CachedHash::verify_hash(stored));
+        let other = Rc::new("bar".to_string());
+        let entry = map.entry_ref(&other);
+        assert!(!entry.is_occupied());
+        assert_eq!(
+            *entry.and_modify(|value| *value += 1).or_insert_with(|| 7),
+            7
+        );
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn lookup_with_stateful_build_hasher() {
+        let mut map = CachedHashMap::with_build_hasher(RandomState::new());
+        let key = CachedHash::new_with_build_hasher(1u64, map.build_hasher().clone());
+        map.insert(key, "one");
+        assert_eq!(map.get(&1), Some(&"one"));
+        assert_eq!(map.get(&2), None);
+    }
+
+    #[test]
+    fn lookup_on_zero_hash_collision() {
+        use nohash_hasher::NoHashHasher;
+
+        let mut map: CachedHashMap<u64, &str, BuildHasherDefault<NoHashHasher<u64>>> =
+            CachedHashMap::default();
+        map.insert(CachedHash::new_with_hasher(0), "zero");
+        map.insert(CachedHash::new_with_hasher(1), "one");
+        assert_eq!(map.get(&0), Some(&"zero"));
+        assert_eq!(map.get(&1), Some(&"one"));
+    }
+
+    #[test]
+    fn moving_between_maps() {
+        let mut map1: CachedHashMap<_, _> = (0..100)
+            .map(|i| (CachedHash::new(i.to_string()), i))
+            .collect();
+        let mut map2 = CachedHashMap::new();
+        map2.extend(map1.drain());
+        assert!(map1.is_empty());
+        assert_eq!(map2.len(), 100);
+        for i in 0..100 {
+            assert_eq!(map2.get(&i.to_string()), Some(&i));
+        }
+    }
+}
@@ -0,0 +1,296 @@
+use core::cmp::Ordering;
+use core::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
+#[cfg(feature = "std")]
+use std::collections::hash_map::DefaultHasher;
+
+use crate::cachedhash::compute_internal_hash;
+use crate::sentinel::{BumpZero, InternalHash, SentinelPolicy, Slot};
+
+/// The cache field of a struct using `#[derive(CachedHash)]` (with the `derive`
+/// feature).
+///
+/// Wrapping a whole struct in [`CachedHash`](struct@crate::CachedHash) invalidates
+/// the hash on every mutable access, even to fields that do not take part in
+/// hashing. Embedding a [`HashCache`] instead lets the derive generate a [`Hash`]
+/// implementation and setters that invalidate the cached hash only when a hashed
+/// field changes:
+///
+/// ```
+/// # #[cfg(feature = "derive")] {
+/// use cachedhash::{CachedHash, HashCache};
+///
+/// #[derive(CachedHash, PartialEq, Eq)]
+/// struct Document {
+///     #[hash]
+///     text: String,
+///     #[hash(skip)]
+///     views: u64,
+///     cache: HashCache,
+/// }
+///
+/// let mut document = Document {
+///     text: "foo".to_string(),
+///     views: 0,
+///     cache: HashCache::new(),
+/// };
+/// document.set_views(document.views() + 1); // keeps the cached hash
+/// document.text_mut().push('d'); // invalidates it
+/// # }
+/// ```
+///
+/// The internal hash is computed from the hashed fields in declaration order,
+/// the same way `BH` would hash a tuple of them. A struct with hashed fields
+/// `a` and `b` therefore hashes the same as `CachedHash<(A, B), BH, P>` holding
+/// the same values, and a struct with a single hashed field `a` the same as
+/// `CachedHash<A, BH, P>`.
+///
+/// [`HashCache`] compares equal to any other [`HashCache`] so deriving
+/// [`PartialEq`], [`Eq`], [`PartialOrd`] and [`Ord`] on the struct only compares
+/// the other fields. Like with [`CachedHash`](struct@crate::CachedHash) the hashed
+/// fields cannot use interior mutability in a way that affects the hash unless
+/// [`HashCache::invalidate_hash`] is called afterwards.
+///
+/// Without the `std` feature there is no [`DefaultHasher`] so `BH` has no
+/// default and needs to be always specified.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct HashCache<
+    BH: BuildHasher = BuildHasherDefault<DefaultHasher>,
+    P: SentinelPolicy = BumpZero,
+> {
+    hash: P::AtomicSlot,
+    build_hasher: BH,
+}
+
+/// The cache field of a struct using `#[derive(CachedHash)]`.
+///
+/// This is the `no_std` version of [`HashCache`] which requires the `BH`
+/// [`BuildHasher`] to be always specified. See the documentation of the crate
+/// built with the `std` feature for details.
+#[cfg(not(feature = "std"))]
+#[derive(Debug)]
+pub struct HashCache<BH: BuildHasher, P: SentinelPolicy = BumpZero> {
+    hash: P::AtomicSlot,
+    build_hasher: BH,
+}
+
+#[cfg(feature = "std")]
+impl HashCache {
+    /// Creates a new empty [`HashCache`] using [`DefaultHasher`].
+    #[must_use]
+    pub fn new() -> Self {
+        Self::new_with_hasher()
+    }
+}
+
+impl<H: Hasher + Default> HashCache<BuildHasherDefault<H>> {
+    /// Creates a new empty [`HashCache`] using a provided hasher type
+    /// implementing [`Default`].
+    #[must_use]
+    pub fn new_with_hasher() -> Self {
+        Self::new_with_build_hasher(BuildHasherDefault::default())
+    }
+}
+
+impl<BH: BuildHasher> HashCache<BH> {
+    /// Creates a new empty [`HashCache`] with the given [`BuildHasher`].
+    pub const fn new_with_build_hasher(build_hasher: BH) -> Self {
+        Self::new_with_sentinel(build_hasher, BumpZero)
+    }
+}
+
+impl<BH: BuildHasher, P: SentinelPolicy> HashCache<BH, P> {
+    /// Creates a new empty [`HashCache`] with the given [`BuildHasher`] using
+    /// the given [`SentinelPolicy`].
+    pub const fn new_with_sentinel(build_hasher: BH, _sentinel: P) -> Self {
+        Self {
+            hash: <P::AtomicSlot as Slot<P::Hash>>::NONE,
+            build_hasher,
+        }
+    }
+
+    /// Returns the cached internal hash if it is currently cached.
+    #[inline]
+    #[must_use]
+    pub fn cached_hash(&self) -> Option<P::Hash> {
+        self.hash.get()
+    }
+
+    /// Returns the internal hash of `fields`, computing and caching it if
+    /// necessary.
+    ///
+    /// `fields` has to be the same every time, the derive passes a tuple of
+    /// references to the hashed fields.
+    #[inline]
+    #[must_use]
+    pub fn internal_hash<T: Hash + ?Sized>(&self, fields: &T) -> P::Hash {
+        if let Some(hash) = self.hash.get() {
+            #[cfg(feature = "instrument")]
+            crate::stats::hit::<BH>();
+            #[cfg(feature = "verify")]
+            crate::verify::check_cached_hash::<T>(
+                hash.to_u64(),
+                compute_internal_hash::<_, _, P>(fields, &self.build_hasher).to_u64(),
+            );
+            return hash;
+        }
+        self.store_internal_hash(fields)
+    }
+
+    /// Explicitly invalidates the cached hash.
+    ///
+    /// The setters generated by the derive do this whenever a hashed field is
+    /// modified. Call this manually if a hashed field uses interior mutability
+    /// in a way that affects the hash.
+    #[inline]
+    pub fn invalidate_hash(&mut self) {
+        #[cfg(feature = "instrument")]
+        crate::stats::explicit_invalidation::<BH>();
+        self.hash.set(None);
+    }
+
+    /// Invalidates the cached hash because a hashed field is about to be
+    /// modified. Used by the derive.
+    #[doc(hidden)]
+    #[inline]
+    pub fn invalidate_for_mutation(&mut self) {
+        #[cfg(feature = "instrument")]
+        crate::stats::mutable_invalidation::<BH>();
+        self.hash.set(None);
+    }
+
+    /// Feeds the internal hash of the fields of `S` into `state`, the same way
+    /// the [`Hash`] implementation of [`CachedHash`](struct@crate::CachedHash) does.
+    /// Used by the derive.
+    #[doc(hidden)]
+    #[inline]
+    pub fn hash_fields<S: ?Sized, T: Hash + ?Sized, H2: Hasher>(&self, fields: &T, state: &mut H2) {
+        if let Some(hash) = self.hash.get() {
+            #[cfg(feature = "instrument")]
+            crate::stats::hit::<BH>();
+            #[cfg(feature = "verify")]
+            crate::verify::check_cached_hash::<S>(
+                hash.to_u64(),
+                compute_internal_hash::<_, _, P>(fields, &self.build_hasher).to_u64(),
+            );
+            hash.write_to(state);
+        } else {
+            self.store_internal_hash(fields).write_to(state);
+        }
+    }
+
+    /// Computes the internal hash of `fields` and caches it.
+    #[inline]
+    fn store_internal_hash<T: Hash + ?Sized>(&self, fields: &T) -> P::Hash {
+        #[cfg(feature = "instrument")]
+        crate::stats::miss::<BH>();
+        let hash = compute_internal_hash::<_, _, P>(fields, &self.build_hasher);
+        self.hash.set(Some(hash));
+        hash
+    }
+}
+
+impl<BH: BuildHasher + Clone, P: SentinelPolicy> Clone for HashCache<BH, P> {
+    fn clone(&self) -> Self {
+        Self {
+            hash: self.hash.clone(),
+            build_hasher: self.build_hasher.clone(),
+        }
+    }
+}
+
+impl<BH: BuildHasher + Default, P: SentinelPolicy> Default for HashCache<BH, P> {
+    fn default() -> Self {
+        Self::new_with_sentinel(BH::default(), P::default())
+    }
+}
+
+/// All [`HashCache`]s are equal so that they do not affect derived comparisons.
+impl<BH: BuildHasher, P: SentinelPolicy> PartialEq for HashCache<BH, P> {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl<BH: BuildHasher, P: SentinelPolicy> Eq for HashCache<BH, P> {}
+
+impl<BH: BuildHasher, P: SentinelPolicy> PartialOrd for HashCache<BH, P> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<BH: BuildHasher, P: SentinelPolicy> Ord for HashCache<BH, P> {
+    fn cmp(&self, _other: &Self) -> Ordering {
+        Ordering::Equal
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    use super::*;
+    use crate::{BuildPassThroughHasher, CachedHash};
+
+    fn calculate_hash<T: Hash>(t: &T) -> u64 {
+        let mut s = DefaultHasher::default();
+        t.hash(&mut s);
+        s.finish()
+    }
+
+    #[test]
+    fn internal_hash_matches_cached_hash() {
+        let cache = HashCache::new();
+        let expected = CachedHash::new(("foo", 1));
+        assert_eq!(cache.cached_hash(), None);
+        assert_eq!(
+            cache.internal_hash(&(&"foo", &1)),
+            CachedHash::internal_hash(&expected)
+        );
+        assert_eq!(cache.cached_hash(), CachedHash::cached_hash(&expected));
+    }
+
+    #[test]
+    fn hash_fields_matches_cached_hash() {
+        let cache = HashCache::new();
+        let mut state = DefaultHasher::default();
+        cache.hash_fields::<(), _, _>(&"foo", &mut state);
+        assert_eq!(state.finish(), calculate_hash(&CachedHash::new("foo")));
+        // the second time the cached hash is used
+        let mut state = DefaultHasher::default();
+        cache.hash_fields::<(), _, _>(&"foo", &mut state);
+        assert_eq!(state.finish(), calculate_hash(&CachedHash::new("foo")));
+    }
+
+    #[test]
+    fn invalidate() {
+        let mut cache = HashCache::new_with_build_hasher(BuildPassThroughHasher::default());
+        assert_eq!(cache.internal_hash(&1_u64).get(), 1);
+        #[cfg(not(feature = "verify"))] // `verify` panics on the stale hash
+        assert_eq!(cache.internal_hash(&2_u64).get(), 1);
+        cache.invalidate_hash();
+        assert_eq!(cache.internal_hash(&2_u64).get(), 2);
+        cache.invalidate_for_mutation();
+        assert_eq!(cache.cached_hash(), None);
+    }
+
+    #[test]
+    fn clone_keeps_the_hash() {
+        let cache = HashCache::new();
+        let _ = cache.internal_hash(&"foo");
+        let clone = cache.clone();
+        assert_eq!(clone.cached_hash(), cache.cached_hash());
+        assert!(clone.cached_hash().is_some());
+    }
+
+    #[test]
+    fn all_equal() {
+        let foo = HashCache::new();
+        let _ = foo.internal_hash(&"foo");
+        let bar = HashCache::new();
+        assert_eq!(foo, bar);
+        assert_eq!(foo.cmp(&bar), Ordering::Equal);
+    }
+}
@@ -0,0 +1,517 @@
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Debug;
+use std::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
+use std::ops::Deref;
+
+use crate::cachedhash::cached_hashes_differ;
+
+/// A collection whose hash does not depend on the order of its elements so
+/// that [`IncrementalCachedHash`] can update it one element at a time.
+///
+/// Implemented for [`HashSet`], [`BTreeSet`] and [`Vec`] which is treated as
+/// a multiset, see [`IncrementalHashSet`], [`IncrementalBTreeSet`] and
+/// [`IncrementalMultiset`].
+pub trait IncrementalCollection {
+    /// The type of the elements.
+    type Element: Hash;
+
+    /// Inserts `element` and returns `true` if it was added, `false` if the
+    /// collection did not change (for example because a set already
+    /// contained it).
+    fn insert_element(&mut self, element: Self::Element) -> bool;
+
+    /// Removes one element equal to `element` and returns it, or [`None`]
+    /// if there is no such element.
+    fn remove_element(&mut self, element: &Self::Element) -> Option<Self::Element>;
+
+    /// Returns an iterator over all the elements in any order.
+    fn elements(&self) -> impl Iterator<Item = &Self::Element>;
+
+    /// Returns the number of elements.
+    fn len(&self) -> usize;
+
+    /// Returns `true` if there are no elements.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns `true` if both collections contain the same elements the same
+    /// number of times, regardless of their order.
+    fn same_elements(&self, other: &Self) -> bool;
+}
+
+impl<T: Hash + Eq, S: BuildHasher> IncrementalCollection for HashSet<T, S> {
+    type Element = T;
+
+    fn insert_element(&mut self, element: T) -> bool {
+        self.insert(element)
+    }
+
+    fn remove_element(&mut self, element: &T) -> Option<T> {
+        self.take(element)
+    }
+
+    fn elements(&self) -> impl Iterator<Item = &T> {
+        self.iter()
+    }
+
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn same_elements(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+impl<T: Hash + Ord> IncrementalCollection for BTreeSet<T> {
+    type Element = T;
+
+    fn insert_element(&mut self, element: T) -> bool {
+        self.insert(element)
+    }
+
+    fn remove_element(&mut self, element: &T) -> Option<T> {
+        self.take(element)
+    }
+
+    fn elements(&self) -> impl Iterator<Item = &T> {
+        self.iter()
+    }
+
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn same_elements(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+/// A [`Vec`] is treated as a multiset: the order of the elements is ignored
+/// but their number of occurrences is not. Removing an element swaps the last
+/// element into its place.
+impl<T: Hash + Eq> IncrementalCollection for Vec<T> {
+    type Element = T;
+
+    fn insert_element(&mut self, element: T) -> bool {
+        self.push(element);
+        true
+    }
+
+    fn remove_element(&mut self, element: &T) -> Option<T> {
+        let index = self.iter().position(|other| other == element)?;
+        Some(self.swap_remove(index))
+    }
+
+    fn elements(&self) -> impl Iterator<Item = &T> {
+        self.iter()
+    }
+
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn same_elements(&self, other: &Self) -> bool {
+        if self.len() != other.len() {
+            return false;
+        }
+        let mut counts = HashMap::<&T, isize>::with_capacity(self.len());
+        for element in self {
+            *counts.entry(element).or_default() += 1;
+        }
+        for element in other {
+            match counts.get_mut(element) {
+                Some(count) if *count > 0 => *count -= 1,
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+/// Wraps a set-like collection and keeps its hash up to date as elements are
+/// inserted and removed, without ever rehashing the whole collection.
+///
+/// Unlike [`CachedHash`](struct@crate::CachedHash), which invalidates the
+/// hash whenever the value is accessed mutably, [`IncrementalCachedHash`]
+/// combines the hashes of the individual elements with a commutative operation
+/// (a wrapping sum of the hash of each element computed by `BH`). Inserting or
+/// removing an element adds or subtracts its hash in O(1) so the hash never
+/// needs to be recomputed. The internal hash is then computed from this sum
+/// and the number of elements, again in O(1).
+///
+/// The collection can only be modified through the associated functions of
+/// [`IncrementalCachedHash`]: [`IncrementalCachedHash::insert_element`],
+/// [`IncrementalCachedHash::remove_element`] and [`Extend`] update the hash
+/// incrementally while [`IncrementalCachedHash::modify`] gives full mutable
+/// access and recomputes the hash from scratch afterwards. For reading it
+/// implements [`Deref`].
+///
+/// The hash is order-independent by construction so it differs from the
+/// [`Hash`] implementation of the collection itself (and of
+/// [`CachedHash`](struct@crate::CachedHash) wrapping it), even for
+/// [`BTreeSet`]. On the other hand it makes [`HashSet`] hashable.
+///
+/// As with [`CachedHash`](struct@crate::CachedHash) the elements cannot use
+/// interior mutability in a way that affects their hash. If they do, the
+/// hash of a modified element can no longer be subtracted when it is removed.
+/// Use [`IncrementalCachedHash::modify`] after such changes.
+///
+/// Only available with the `std` feature.
+#[derive(Debug, Clone)]
+pub struct IncrementalCachedHash<C, BH: BuildHasher = BuildHasherDefault<DefaultHasher>> {
+    sum: u64,
+    build_hasher: BH,
+    collection: C,
+}
+
+/// An [`IncrementalCachedHash`] of a [`HashSet`].
+pub type IncrementalHashSet<T, S = RandomState, BH = BuildHasherDefault<DefaultHasher>> =
+    IncrementalCachedHash<HashSet<T, S>, BH>;
+
+/// An [`IncrementalCachedHash`] of a [`BTreeSet`].
+pub type IncrementalBTreeSet<T, BH = BuildHasherDefault<DefaultHasher>> =
+    IncrementalCachedHash<BTreeSet<T>, BH>;
+
+/// An [`IncrementalCachedHash`] of a [`Vec`] treated as a multiset: equality
+/// and hashing ignore the order of the elements.
+pub type IncrementalMultiset<T, BH = BuildHasherDefault<DefaultHasher>> =
+    IncrementalCachedHash<Vec<T>, BH>;
+
+impl<C: IncrementalCollection> IncrementalCachedHash<C> {
+    /// Creates a new [`IncrementalCachedHash`] with the given collection
+    /// using [`DefaultHasher`], hashing all its elements.
+    pub fn new(collection: C) -> Self {
+        Self::new_with_hasher(collection)
+    }
+}
+
+impl<C: IncrementalCollection, H: Hasher + Default>
+    IncrementalCachedHash<C, BuildHasherDefault<H>>
+{
+    /// Creates a new [`IncrementalCachedHash`] with the given collection using
+    /// a provided hasher type implementing [`Default`], hashing all its elements.
+    pub fn new_with_hasher(collection: C) -> Self {
+        Self::new_with_build_hasher(collection, BuildHasherDefault::default())
+    }
+}
+
+impl<C: IncrementalCollection, BH: BuildHasher> IncrementalCachedHash<C, BH> {
+    /// Creates a new [`IncrementalCachedHash`] with the given collection and
+    /// [`BuildHasher`], hashing all its elements.
+    pub fn new_with_build_hasher(collection: C, build_hasher: BH) -> Self {
+        let sum = sum_hashes(&collection, &build_hasher);
+        Self {
+            sum,
+            build_hasher,
+            collection,
+        }
+    }
+
+    /// Explicitly returns an immutable reference to the stored collection.
+    #[inline]
+    #[must_use]
+    pub const fn get(this: &Self) -> &C {
+        &this.collection
+    }
+
+    /// Destructs the [`IncrementalCachedHash`] and returns the stored collection.
+    #[inline]
+    #[must_use]
+    pub fn take_value(this: Self) -> C {
+        this.collection
+    }
+
+    /// Returns the internal hash of the collection in O(1).
+    ///
+    /// It is computed by `BH` from the number of elements and the wrapping
+    /// sum of the hashes of the elements.
+    #[inline]
+    #[must_use]
+    pub fn internal_hash(this: &Self) -> u64 {
+        let len = this.collection.len() as u64;
+        this.build_hasher.hash_one((len, this.sum))
+    }
+
+    /// Inserts `element` into the collection and adds its hash if it was
+    /// added. Returns whether it was added.
+    pub fn insert_element(this: &mut Self, element: C::Element) -> bool {
+        let hash = this.build_hasher.hash_one(&element);
+        let added = this.collection.insert_element(element);
+        if added {
+            this.sum = this.sum.wrapping_add(hash);
+        }
+        added
+    }
+
+    /// Removes one element equal to `element` from the collection and
+    /// subtracts its hash. Returns the removed element if there was one.
+    pub fn remove_element(this: &mut Self, element: &C::Element) -> Option<C::Element> {
+        let removed = this.collection.remove_element(element)?;
+        this.sum = this.sum.wrapping_sub(this.build_hasher.hash_one(&removed));
+        Some(removed)
+    }
+
+    /// Gives mutable access to the collection through `f` and recomputes
+    /// the hash from scratch afterwards, which takes O(n).
+    ///
+    /// Use this for changes that are not a single insertion or removal, such
+    /// as `retain`, or after modifying elements through interior mutability.
+    pub fn modify<R>(this: &mut Self, f: impl FnOnce(&mut C) -> R) -> R {
+        let result = f(&mut this.collection);
+        this.sum = sum_hashes(&this.collection, &this.build_hasher);
+        result
+    }
+
+    /// Recomputes the hash from scratch and checks that it matches the one
+    /// maintained incrementally.
+    ///
+    /// A mismatch means that an element was modified through interior
+    /// mutability in a way that affected its hash.
+    #[must_use]
+    pub fn verify_hash(this: &Self) -> bool {
+        this.sum == sum_hashes(&this.collection, &this.build_hasher)
+    }
+}
+
+/// Returns the wrapping sum of the hashes of all elements of `collection`.
+fn sum_hashes<C: IncrementalCollection, BH: BuildHasher>(collection: &C, build_hasher: &BH) -> u64 {
+    collection.elements().fold(0, |sum, element| {
+        sum.wrapping_add(build_hasher.hash_one(element))
+    })
+}
+
+/// When `BH` is zero-sized and the hashes differ the collections are not
+/// compared, see the `Equality` section of [`CachedHash`](struct@crate::CachedHash).
+impl<C: IncrementalCollection, BH: BuildHasher> PartialEq for IncrementalCachedHash<C, BH> {
+    fn eq(&self, other: &Self) -> bool {
+        if cached_hashes_differ::<BH, _>(Some(self.sum), Some(other.sum)) {
+            return false;
+        }
+        self.collection.same_elements(&other.collection)
+    }
+}
+
+impl<C: IncrementalCollection, BH: BuildHasher> Eq for IncrementalCachedHash<C, BH> {}
+
+impl<C: IncrementalCollection, BH: BuildHasher> Hash for IncrementalCachedHash<C, BH> {
+    fn hash<H2: Hasher>(&self, state: &mut H2) {
+        state.write_u64(Self::internal_hash(self));
+    }
+}
+
+impl<C, BH: BuildHasher> Deref for IncrementalCachedHash<C, BH> {
+    type Target = C;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.collection
+    }
+}
+
+impl<C, BH: BuildHasher> AsRef<C> for IncrementalCachedHash<C, BH> {
+    fn as_ref(&self) -> &C {
+        &self.collection
+    }
+}
+
+impl<C: IncrementalCollection + Default, BH: BuildHasher + Default> Default
+    for IncrementalCachedHash<C, BH>
+{
+    fn default() -> Self {
+        Self::new_with_build_hasher(C::default(), BH::default())
+    }
+}
+
+impl<C: IncrementalCollection, H: Hasher + Default> From<C>
+    for IncrementalCachedHash<C, BuildHasherDefault<H>>
+{
+    fn from(collection: C) -> Self {
+        Self::new_with_hasher(collection)
+    }
+}
+
+impl<C: IncrementalCollection, BH: BuildHasher> Extend<C::Element>
+    for IncrementalCachedHash<C, BH>
+{
+    fn extend<I: IntoIterator<Item = C::Element>>(&mut self, iter: I) {
+        for element in iter {
+            Self::insert_element(self, element);
+        }
+    }
+}
+
+impl<C: IncrementalCollection + Default, BH: BuildHasher + Default> FromIterator<C::Element>
+    for IncrementalCachedHash<C, BH>
+{
+    fn from_iter<I: IntoIterator<Item = C::Element>>(iter: I) -> Self {
+        let mut this = Self::default();
+        this.extend(iter);
+        this
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::HashSet;
+    use std::hash::{Hash, Hasher};
+
+    use oorandom::Rand64;
+
+    use super::*;
+
+    fn calculate_hash<T: Hash>(t: &T) -> u64 {
+        let mut s = DefaultHasher::default();
+        t.hash(&mut s);
+        s.finish()
+    }
+
+    /// Applies random insertions, removals and bulk changes to `incremental`
+    /// and checks after every step that the hash matches a fresh
+    /// [`IncrementalCachedHash`] of the same collection.
+    fn check_random_operations<C>(seed: u128, mut incremental: IncrementalCachedHash<C>)
+    where
+        C: IncrementalCollection<Element = u64> + Clone + Debug,
+    {
+        let mut rng = Rand64::new(seed);
+        for _ in 0..500 {
+            // few distinct values so that duplicates and removals are common
+            let element = rng.rand_range(0..50);
+            match rng.rand_range(0..10) {
+                0..=4 => {
+                    IncrementalCachedHash::insert_element(&mut incremental, element);
+                }
+                5..=8 => {
+                    IncrementalCachedHash::remove_element(&mut incremental, &element);
+                }
+                _ => {
+                    let removed = IncrementalCachedHash::get(&incremental)
+                        .elements()
+                        .take(5)
+                        .copied()
+                        .collect::<Vec<_>>();
+                    for element in &removed {
+                        IncrementalCachedHash::remove_element(&mut incremental, element);
+                    }
+                    incremental.extend(removed.into_iter().rev());
+                }
+            }
+            let fresh =
+                IncrementalCachedHash::new(IncrementalCachedHash::get(&incremental).clone());
+            assert!(IncrementalCachedHash::verify_hash(&incremental));
+            assert_eq!(
+                IncrementalCachedHash::internal_hash(&incremental),
+                IncrementalCachedHash::internal_hash(&fresh),
+                "{incremental:?}"
+            );
+            assert_eq!(incremental, fresh);
+        }
+    }
+
+    #[test]
+    fn random_operations_hash_set() {
+        for seed in 0..20 {
+            check_random_operations(seed, IncrementalHashSet::<u64>::default());
+        }
+    }
+
+    #[test]
+    fn random_operations_btree_set() {
+        for seed in 0..20 {
+            check_random_operations(seed, IncrementalBTreeSet::default());
+        }
+    }
+
+    #[test]
+    fn random_operations_multiset() {
+        for seed in 0..20 {
+            check_random_operations(seed, IncrementalMultiset::default());
+        }
+    }
+
+    #[test]
+    fn order_independent() {
+        let mut rng = Rand64::new(42);
+        let mut elements = (0..1000).map(|_| rng.rand_u64()).collect::<Vec<_>>();
+        let forward = IncrementalMultiset::new(elements.clone());
+        elements.reverse();
+        let backward = IncrementalMultiset::new(elements.clone());
+        assert_eq!(calculate_hash(&forward), calculate_hash(&backward));
+        assert_eq!(forward, backward);
+        assert_ne!(*forward, *backward);
+
+        let hash_set = IncrementalHashSet::<_>::new(elements.iter().copied().collect());
+        let btree_set = IncrementalBTreeSet::new(elements.iter().copied().collect());
+        assert_eq!(
+            IncrementalCachedHash::internal_hash(&hash_set),
+            IncrementalCachedHash::internal_hash(&btree_set)
+        );
+    }
+
+    #[test]
+    fn multiset_counts_duplicates() {
+        let once = IncrementalMultiset::new(vec![1, 2]);
+        let twice = IncrementalMultiset::new(vec![1, 1, 2]);
+        assert_ne!(once, twice);
+        assert_ne!(calculate_hash(&once), calculate_hash(&twice));
+        assert_ne!(
+            IncrementalMultiset::new(vec![1, 1, 2]),
+            IncrementalMultiset::new(vec![1, 2, 2])
+        );
+        assert_eq!(
+            IncrementalMultiset::new(vec![2, 1, 1]),
+            IncrementalMultiset::new(vec![1, 2, 1])
+        );
+    }
+
+    #[test]
+    fn set_ignores_duplicates() {
+        let mut set = IncrementalBTreeSet::new(BTreeSet::from([1, 2]));
+        let hash = IncrementalCachedHash::internal_hash(&set);
+        assert!(!IncrementalCachedHash::insert_element(&mut set, 1));
+        assert_eq!(IncrementalCachedHash::internal_hash(&set), hash);
+        assert_eq!(IncrementalCachedHash::remove_element(&mut set, &3), None);
+        assert_eq!(IncrementalCachedHash::internal_hash(&set), hash);
+    }
+
+    #[test]
+    fn modify_recomputes() {
+        let mut set = (0..100).collect::<IncrementalHashSet<u32>>();
+        let removed = IncrementalCachedHash::modify(&mut set, |set| {
+            set.retain(|element| element % 2 == 0);
+            set.len()
+        });
+        assert_eq!(removed, 50);
+        assert!(IncrementalCachedHash::verify_hash(&set));
+        assert_eq!(set, (0..100).step_by(2).collect());
+    }
+
+    #[test]
+    fn set_of_hash_sets() {
+        let mut sets = (0..10)
+            .map(|i| (0..i).collect::<IncrementalHashSet<u32>>())
+            .collect::<HashSet<_>>();
+        let mut five = (0..5).rev().collect::<IncrementalHashSet<u32>>();
+        assert!(sets.contains(&five));
+        IncrementalCachedHash::insert_element(&mut five, 10);
+        assert!(!sets.contains(&five));
+        assert!(sets.insert(five));
+        assert_eq!(sets.len(), 11);
+    }
+
+    #[test]
+    fn take_value() {
+        let set = IncrementalBTreeSet::new(BTreeSet::from([3, 1, 2]));
+        assert_eq!(set.len(), 3);
+        assert!(set.contains(&2));
+        assert_eq!(
+            IncrementalCachedHash::take_value(set)
+                .into_iter()
+                .collect::<Vec<_>>(),
+            [1, 2, 3]
+        );
+    }
+}
@@ -0,0 +1,415 @@
+use std::borrow::Borrow;
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
+use std::num::NonZeroU64;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::collections::{KeyQuery, Lookup};
+use crate::{BuildPassThroughHasher, CachedHash, LocalCachedHash};
+
+/// Implements an interner and the handles it creates.
+///
+/// The interner `$interner` stores the values as `$ptr<$wrapper<T, BH>>` in a
+/// set protected by `$cell`, which `$lock` gives access to. The handle
+/// `$interned` wraps such a pointer. Both are documented by the given
+/// attributes.
+macro_rules! impl_interner {
+    (
+        $(#[$interned_meta:meta])*
+        $interned:ident,
+        $(#[$interner_meta:meta])*
+        $interner:ident,
+        $ptr:ident,
+        $wrapper:ident,
+        $cell:ident,
+        $lock:path $(,)?
+    ) => {
+        $(#[$interned_meta])*
+        #[derive(Debug)]
+        pub struct $interned<T, BH: BuildHasher = BuildHasherDefault<DefaultHasher>> {
+            inner: $ptr<$wrapper<T, BH>>,
+        }
+
+        impl<T, BH: BuildHasher> $interned<T, BH> {
+            /// Explicitly returns an immutable reference to the interned value.
+            #[inline]
+            #[must_use]
+            pub fn get(this: &Self) -> &T {
+                $wrapper::get(&this.inner)
+            }
+
+            /// Returns the interned value together with its cached hash.
+            #[inline]
+            #[must_use]
+            pub fn as_cached_hash(this: &Self) -> &$wrapper<T, BH> {
+                &this.inner
+            }
+
+            /// Returns the internal hash, which is always cached.
+            #[inline]
+            #[must_use]
+            pub fn internal_hash(this: &Self) -> NonZeroU64
+            where
+                T: Hash,
+            {
+                $wrapper::internal_hash(&this.inner)
+            }
+
+            /// Returns `true` if both handles point to the same interned value.
+            #[inline]
+            #[must_use]
+            pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+                $ptr::ptr_eq(&this.inner, &other.inner)
+            }
+        }
+
+        impl<T, BH: BuildHasher> Clone for $interned<T, BH> {
+            fn clone(&self) -> Self {
+                Self {
+                    inner: $ptr::clone(&self.inner),
+                }
+            }
+        }
+
+        impl<T, BH: BuildHasher> PartialEq for $interned<T, BH> {
+            fn eq(&self, other: &Self) -> bool {
+                Self::ptr_eq(self, other)
+            }
+        }
+
+        impl<T, BH: BuildHasher> Eq for $interned<T, BH> {}
+
+        impl<T: Hash, BH: BuildHasher> Hash for $interned<T, BH> {
+            fn hash<H2: Hasher>(&self, state: &mut H2) {
+                self.inner.hash(state);
+            }
+        }
+
+        impl<T, BH: BuildHasher> AsRef<T> for $interned<T, BH> {
+            fn as_ref(&self) -> &T {
+                Self::get(self)
+            }
+        }
+
+        impl<T, BH: BuildHasher> Deref for $interned<T, BH> {
+            type Target = T;
+
+            #[inline]
+            fn deref(&self) -> &Self::Target {
+                Self::get(self)
+            }
+        }
+
+        $(#[$interner_meta])*
+        #[derive(Debug)]
+        pub struct $interner<T, BH: BuildHasher = BuildHasherDefault<DefaultHasher>> {
+            set: $cell<HashSet<$ptr<$wrapper<T, BH>>, BuildPassThroughHasher>>,
+            build_hasher: BH,
+        }
+
+        impl<T: Eq + Hash> $interner<T> {
+            /// Creates an empty interner using [`DefaultHasher`].
+            #[must_use]
+            pub fn new() -> Self {
+                Self::default()
+            }
+        }
+
+        impl<T: Eq + Hash, BH: BuildHasher> $interner<T, BH> {
+            /// Creates an empty interner hashing the values using `build_hasher`.
+            pub fn with_build_hasher(build_hasher: BH) -> Self {
+                Self {
+                    set: $cell::default(),
+                    build_hasher,
+                }
+            }
+
+            /// Returns a reference to the [`BuildHasher`] used to hash the values.
+            #[inline]
+            pub const fn build_hasher(&self) -> &BH {
+                &self.build_hasher
+            }
+
+            /// Returns a handle to the interned value equal to `value`, interning
+            /// `value` if there is none.
+            pub fn intern(&self, value: T) -> $interned<T, BH>
+            where
+                BH: Clone,
+            {
+                let value =
+                    $wrapper::new_hashed_with_build_hasher(value, self.build_hasher.clone());
+                let mut set = self.lock();
+                let found = set.get(&value as &dyn KeyQuery<T>).map($ptr::clone);
+                let inner = found.unwrap_or_else(|| {
+                    let inner = $ptr::new(value);
+                    set.insert($ptr::clone(&inner));
+                    inner
+                });
+                drop(set);
+                $interned { inner }
+            }
+
+            /// Returns a handle to the interned value equal to `value`, interning
+            /// a clone of `value` if there is none.
+            pub fn intern_ref(&self, value: &T) -> $interned<T, BH>
+            where
+                T: Clone,
+                BH: Clone,
+            {
+                let mut set = self.lock();
+                let found = set
+                    .get(&Lookup::new(value, &self.build_hasher) as &dyn KeyQuery<T>)
+                    .map($ptr::clone);
+                let inner = found.unwrap_or_else(|| {
+                    let inner = $ptr::new($wrapper::new_hashed_with_build_hasher(
+                        value.clone(),
+                        self.build_hasher.clone(),
+                    ));
+                    set.insert($ptr::clone(&inner));
+                    inner
+                });
+                drop(set);
+                $interned { inner }
+            }
+
+            /// Returns a handle to the interned value equal to `value` without
+            /// interning it if there is none.
+            pub fn get(&self, value: &T) -> Option<$interned<T, BH>> {
+                self.lock()
+                    .get(&Lookup::new(value, &self.build_hasher) as &dyn KeyQuery<T>)
+                    .map(|inner| $interned {
+                        inner: $ptr::clone(inner),
+                    })
+            }
+
+            /// Returns the number of interned values, including the ones that are no
+            /// longer referenced.
+            #[must_use]
+            pub fn len(&self) -> usize {
+                self.lock().len()
+            }
+
+            /// Returns `true` if no values are interned.
+            #[must_use]
+            pub fn is_empty(&self) -> bool {
+                self.lock().is_empty()
+            }
+
+            /// Removes the interned values which are not referenced by any handle and
+            /// returns how many were removed.
+            ///
+            /// Interning such a value again creates a new allocation, which is fine as
+            /// there are no handles it needs to be equal to.
+            pub fn collect_garbage(&self) -> usize {
+                let mut garbage = Vec::new();
+                self.lock().retain(|inner| {
+                    // holding the set prevents new handles from being created so
+                    // the count cannot grow meanwhile
+                    if $ptr::strong_count(inner) > 1 {
+                        return true;
+                    }
+                    garbage.push($ptr::clone(inner));
+                    false
+                });
+                // the values are dropped after releasing the set in case their
+                // `Drop` interns
+                garbage.len()
+            }
+
+            fn lock(
+                &self,
+            ) -> impl DerefMut<Target = HashSet<$ptr<$wrapper<T, BH>>, BuildPassThroughHasher>> + '_
+            {
+                $lock(&self.set)
+            }
+        }
+
+        impl<T: Eq + Hash, BH: BuildHasher + Default> Default for $interner<T, BH> {
+            fn default() -> Self {
+                Self::with_build_hasher(BH::default())
+            }
+        }
+
+        #[doc(hidden)]
+        impl<'a, T: Hash + 'a, BH: BuildHasher + 'a> Borrow<dyn KeyQuery<T> + 'a>
+            for $ptr<$wrapper<T, BH>>
+        {
+            fn borrow(&self) -> &(dyn KeyQuery<T> + 'a) {
+                &**self
+            }
+        }
+    };
+}
+
+impl_interner!(
+    /// A handle to a value interned in an [`Interner`].
+    ///
+    /// All handles to equal values created by the same [`Interner`] point to the
+    /// same allocation, so [`Interned`] compares by pointer and hashes by writing
+    /// the internal hash cached when the value was interned. Both are O(1)
+    /// regardless of the size of the value. Handles created by different
+    /// [`Interner`]s are never equal, even for equal values.
+    ///
+    /// Only available with the `std` feature.
+    Interned,
+    /// A thread-safe hash-consing interner handing out [`Interned`] handles.
+    ///
+    /// Interning a value returns a handle to the single stored copy of all equal
+    /// values, hashing the value once with `BH` and caching the hash alongside it.
+    /// The values are stored in a set using
+    /// [`PassThroughHasher`](crate::PassThroughHasher) so lookups use the internal
+    /// hash directly, like [`CachedHashMap`](crate::CachedHashMap) does.
+    ///
+    /// Values are kept even after all their handles are dropped.
+    /// [`Interner::collect_garbage`] removes the values that are no longer
+    /// referenced by any handle.
+    ///
+    /// The set is protected by a [`Mutex`] so the interner can be shared between
+    /// threads. Use [`LocalInterner`] if the values never cross thread boundaries.
+    ///
+    /// Only available with the `std` feature.
+    Interner,
+    Arc,
+    CachedHash,
+    Mutex,
+    lock_ignoring_poison,
+);
+
+impl_interner!(
+    /// A handle to a value interned in a [`LocalInterner`].
+    ///
+    /// The single-threaded version of [`Interned`] which compares by pointer and
+    /// hashes by writing the cached internal hash.
+    ///
+    /// Only available with the `std` feature.
+    LocalInterned,
+    /// A single-threaded version of [`Interner`] handing out [`LocalInterned`]
+    /// handles.
+    ///
+    /// The set is stored in a [`RefCell`] and the values behind [`Rc`]s so neither
+    /// the interner nor its handles can cross thread boundaries. See [`Interner`]
+    /// for details.
+    ///
+    /// Only available with the `std` feature.
+    LocalInterner,
+    Rc,
+    LocalCachedHash,
+    RefCell,
+    RefCell::borrow_mut,
+);
+
+/// Locks `mutex`, recovering the guard if it is poisoned.
+fn lock_ignoring_poison<S>(mutex: &Mutex<S>) -> MutexGuard<'_, S> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Generates the tests shared by `$interner` and its handle `$interned`.
+#[cfg(test)]
+macro_rules! interner_tests {
+    ($module:ident, $interner:ident, $interned:ident) => {
+        mod $module {
+            use std::collections::hash_map::RandomState;
+            use std::collections::HashMap;
+
+            use super::*;
+
+            #[test]
+            fn interning_deduplicates() {
+                let interner = $interner::new();
+                let foo = interner.intern("foo".to_string());
+                let bar = interner.intern("bar".to_string());
+                let foo2 = interner.intern_ref(&"foo".to_string());
+                assert_eq!(foo, foo2);
+                assert!($interned::ptr_eq(&foo, &foo2));
+                assert_ne!(foo, bar);
+                assert_eq!(*foo, "foo");
+                assert_eq!(interner.len(), 2);
+                assert_eq!(interner.get(&"bar".to_string()), Some(bar));
+                assert_eq!(interner.get(&"baz".to_string()), None);
+                assert_eq!(
+                    $interned::internal_hash(&foo),
+                    CachedHash::internal_hash(&CachedHash::new("foo".to_string()))
+                );
+            }
+
+            #[test]
+            fn different_interners_are_not_equal() {
+                let first = $interner::new();
+                let second = $interner::new();
+                assert_ne!(first.intern(1), second.intern(1));
+            }
+
+            #[test]
+            #[allow(clippy::mutable_key_type)]
+            fn handles_as_keys() {
+                let interner = $interner::with_build_hasher(RandomState::new());
+                let mut map = HashMap::<_, _, BuildPassThroughHasher>::default();
+                for i in 0..100 {
+                    map.insert(interner.intern(vec![i; 100]), i);
+                }
+                for i in 0..100 {
+                    assert_eq!(map.get(&interner.intern_ref(&vec![i; 100])), Some(&i));
+                }
+            }
+
+            #[test]
+            fn collect_garbage() {
+                let interner = $interner::new();
+                let foo = interner.intern("foo");
+                let bar = interner.intern("bar");
+                let bar2 = bar.clone();
+                drop(interner.intern("baz"));
+                assert_eq!(interner.len(), 3);
+                assert_eq!(interner.collect_garbage(), 1);
+                assert_eq!(interner.get(&"baz"), None);
+                drop(bar);
+                assert_eq!(interner.collect_garbage(), 0);
+                drop(bar2);
+                assert_eq!(interner.collect_garbage(), 1);
+                assert_eq!(interner.get(&"foo"), Some(foo));
+                assert_eq!(interner.len(), 1);
+                assert!(!interner.is_empty());
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+interner_tests!(interner_tests, Interner, Interned);
+#[cfg(test)]
+interner_tests!(local_interner_tests, LocalInterner, LocalInterned);
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn shared_between_threads() {
+        let interner = Interner::new();
+        let handles = thread::scope(|scope| {
+            let threads = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        (0..100)
+                            .map(|i| interner.intern(i.to_string()))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(interner.len(), 100);
+        for thread_handles in &handles[1..] {
+            assert_eq!(thread_handles, &handles[0]);
+        }
+    }
+}
@@ -1,9 +1,10 @@
-#![forbid(unsafe_code)]
+#![cfg_attr(not(feature = "std"), no_std)]
+#![deny(unsafe_code)] // only allowed in `cachedhash::boxed`, see there
 #![warn(clippy::pedantic)]
 #![warn(clippy::cargo)]
 #![warn(clippy::nursery)]
 
-//! For a type `T`, [`CachedHash<T>`](CachedHash) wraps `T` and
+//! For a type `T`, [`CachedHash<T>`](struct@CachedHash) wraps `T` and
 //! implements [`Hash`](https://doc.rust-lang.org/std/hash/trait.Hash.html) in a way that
 //! caches `T`'s hash value. This is useful when `T` is expensive to hash (for
 //! example if it contains a large vector) and you need to hash it multiple times
@@ -15,12 +16,148 @@
 //! [`AsMut`](https://doc.rust-lang.org/std/convert/trait.AsMut.html),
 //! [`BorrowMut`](https://doc.rust-lang.org/std/borrow/trait.BorrowMut.html)
 //! or explicitly via a provided [associated function](CachedHash::get_mut)).
+//! If you would rather have the hash recomputed right after the modification use
+//! [`CachedHash::modify`] or [`CachedHash::update`].
 //! In order for the hash to be invalidated correctly the stored type cannot use
 //! interior mutability in a way that affects the hash. If this is the case, you
 //! can use [`CachedHash::invalidate_hash`](CachedHash::invalidate_hash)
 //!  to invalidate the hash manually.
+//!
+//! When the stored hash is used to deduplicate a very large number of values,
+//! [`CachedHash128<T>`](CachedHash128) caches a 128-bit fingerprint instead.
+//! When memory is tight and `T` is at most 4-byte aligned,
+//! [`CachedHash32<T>`](CachedHash32) caches only a 32-bit hash.
+//! How a hash that is not computed yet is represented (and which artificial
+//! collision this creates) is chosen by a [`SentinelPolicy`].
+//! If the internal hash is persisted or compared between processes, use
+//! [`StableCachedHash<T>`](StableCachedHash) whose hasher is guaranteed not to change.
+//!
+//! # Features
+//!
+//! - `std` (enabled by default): Uses [`DefaultHasher`](https://doc.rust-lang.org/std/collections/hash_map/struct.DefaultHasher.html)
+//!   as the default hasher and provides [`CachedHashMap`] and [`CachedHashSet`].
+//!   Without it the crate is `no_std` and the `BH` type parameter of [`CachedHash`](struct@CachedHash)
+//!   and [`LocalCachedHash`] has no default so it needs to be always specified.
+//! - `portable-atomic`: Stores the cached hash using the
+//!   [`portable-atomic`](https://docs.rs/portable-atomic) crate. This is needed on
+//!   targets without native 64-bit atomics (depending on the target you might also
+//!   need to enable one of its features such as `critical-section`).
+//! - `verify`: Every time a cached hash is fed into a `Hasher` or returned by
+//!   `internal_hash` the hash is recomputed and compared with the cached one. A mismatch, caused by interior mutability that was not
+//!   followed by [`CachedHash::invalidate_hash`], panics or calls a hook registered
+//!   with `set_stale_hash_hook`. This defeats the purpose of caching so it is only
+//!   meant for debugging.
+//! - `serde`: Implements `Serialize` and `Deserialize` for [`CachedHash`](struct@CachedHash) and
+//!   [`LocalCachedHash`] by (de)serializing only the stored value. The
+//!   `serde_with_hash` module can be used with `#[serde(with = ...)]` to also
+//!   persist the cached internal hash.
+//! - `instrument`: Counts cache hits, misses and invalidations of
+//!   [`CachedHash`](struct@CachedHash) and [`LocalCachedHash`], in total and per `BuildHasher`
+//!   type. `stats()` returns a snapshot and `reset_stats()` starts over. Every
+//!   hash updates shared atomic counters so only enable it to find out whether
+//!   the caching pays off. Without the feature nothing is counted. Implies `std`.
+//! - `derive`: Provides `#[derive(CachedHash)]` from the `cachedhash-derive`
+//!   crate, see [`HashCache`].
+//!
+//! If the values never cross thread boundaries you can use
+//! [`LocalCachedHash<T>`](LocalCachedHash) which stores the cached hash in a
+//! [`Cell`](https://doc.rust-lang.org/std/cell/struct.Cell.html) instead of an atomic.
+//! When only some fields of a struct take part in hashing, `#[derive(CachedHash)]`
+//! (with the `derive` feature) caches the hash in a [`HashCache`] field and
+//! invalidates it only when one of the hashed fields changes.
+//! For sets and multisets, [`IncrementalCachedHash`] keeps an order-independent hash
+//! up to date in O(1) per inserted or removed element instead of rehashing everything.
+//! For long sequences, [`CachedHashVec`] caches a hash per fixed-size chunk and
+//! rehashes only the chunks that were modified.
+//! [`Interner`] (and the single-threaded [`LocalInterner`]) deduplicates values
+//! into [`Interned`] handles which compare by pointer and hash using the cached hash.
+//! [`Memo`] memoizes functions of [`CachedHash`](struct@CachedHash) arguments so that
+//! a cache hit does no hashing, optionally evicting the least recently used entries.
+//! For values shared through reference counting, [`SharedCachedHash<T>`](SharedCachedHash)
+//! keeps the value and its cached hash behind one `Arc` so all clones share the cache.
+//! `T` may also be unsized: `Box<CachedHash<str>>` and `Box<CachedHash<[T]>>` can
+//! be created using [`From`] or [`CachedHash::new_boxed`], and `Arc<CachedHash<str>>`
+//! using [`CachedHash::new_arc`] or by converting such a box with `Arc::from`.
+//!
+//! As the [`Hash`](https://doc.rust-lang.org/std/hash/trait.Hash.html) implementation
+//! of [`CachedHash`](struct@CachedHash) only writes the cached hash into the hasher, there is no need
+//! to hash it again. [`CachedHashMap`] and [`CachedHashSet`] use [`PassThroughHasher`]
+//! which returns the cached hash as is.
+//!
+//! Note that as the hash of `T` and the hash of [`CachedHash<T>`](struct@CachedHash) differ,
+//! looking up `&T` in a `HashMap<CachedHash<T>, V>` through the
+//! [`Borrow<T>`](https://doc.rust-lang.org/std/borrow/trait.Borrow.html) implementation
+//! of [`CachedHash`](struct@CachedHash) does not find the entry. Use [`CachedHashMap`] which supports
+//! lookups by `&T` instead.
+
+#[cfg(all(test, not(feature = "std")))]
+extern crate std;
 
 mod atomic;
 mod cachedhash;
+#[cfg(feature = "std")]
+mod cachedhash128;
+mod cachedhash32;
+#[cfg(feature = "std")]
+mod cachedhashvec;
+#[cfg(feature = "std")]
+mod collections;
+mod hashcache;
+#[cfg(feature = "std")]
+mod incremental;
+#[cfg(feature = "std")]
+mod interner;
+mod localcachedhash;
+#[cfg(feature = "std")]
+mod memo;
+mod passthrough;
+mod sentinel;
+#[cfg(feature = "serde")]
+pub mod serde_with_hash;
+#[cfg(feature = "std")]
+mod sharedcachedhash;
+mod stable;
+#[cfg(feature = "instrument")]
+mod stats;
+#[cfg(feature = "verify")]
+mod verify;
+
+#[cfg(feature = "derive")]
+pub use cachedhash_derive::CachedHash;
+
+pub use crate::cachedhash::{CachedHash, CachedHashGuard, NoEqShortcut, RehashPolicy};
+#[cfg(feature = "std")]
+pub use crate::cachedhash128::{CachedHash128, DoubleHasher, Hasher128};
+pub use crate::cachedhash32::{CachedHash32, CachedHash32Guard};
+#[cfg(feature = "std")]
+pub use crate::cachedhashvec::{CachedHashVec, DEFAULT_CHUNK_LEN};
+#[cfg(feature = "std")]
+pub use crate::collections::{CachedHashMap, CachedHashSet, EntryRef};
+pub use crate::hashcache::HashCache;
+#[cfg(feature = "std")]
+pub use crate::incremental::{
+    IncrementalBTreeSet, IncrementalCachedHash, IncrementalCollection, IncrementalHashSet,
+    IncrementalMultiset,
+};
+#[cfg(feature = "std")]
+pub use crate::interner::{Interned, Interner, LocalInterned, LocalInterner};
+pub use crate::localcachedhash::{LocalCachedHash, LocalCachedHashGuard};
+#[cfg(feature = "std")]
+pub use crate::memo::Memo;
+pub use crate::passthrough::{BuildPassThroughHasher, PassThroughHasher};
+pub use crate::sentinel::{BumpZero, Fold32, NoSentinel, RemixZero, SentinelPolicy};
+#[cfg(feature = "std")]
+pub use crate::sharedcachedhash::SharedCachedHash;
+pub use crate::stable::{BuildStableHasher, StableCachedHash, StableHasher};
+#[cfg(feature = "instrument")]
+pub use crate::stats::{reset_stats, stats, Counts, Stats};
+#[cfg(feature = "verify")]
+pub use crate::verify::{set_stale_hash_hook, take_stale_hash_hook, StaleHash};
 
-pub use crate::cachedhash::CachedHash;
+/// Not public API. Only exported because it appears in public trait
+/// implementations.
+#[doc(hidden)]
+#[cfg(feature = "std")]
+pub mod __private {
+    pub use crate::collections::KeyQuery;
+}
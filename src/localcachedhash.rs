@@ -0,0 +1,63 @@
+use core::hash::BuildHasher;
+#[cfg(feature = "std")]
+use core::hash::BuildHasherDefault;
+#[cfg(feature = "std")]
+use std::collections::hash_map::DefaultHasher;
+
+use crate::cachedhash::wrapper;
+use crate::sentinel::{BumpZero, SentinelPolicy};
+
+/// A single-threaded version of [`CachedHash`](struct@crate::CachedHash).
+///
+/// [`LocalCachedHash`] behaves exactly like [`CachedHash`](struct@crate::CachedHash)
+/// but stores the cached hash in a [`Cell`](core::cell::Cell) instead of an atomic. This makes
+/// hashing a little cheaper at the cost of the type never being [`Sync`].
+/// Use it when the values never cross thread boundaries, for example in
+/// single-threaded hot loops.
+///
+/// The size of [`LocalCachedHash`] is the same as the size of
+/// [`CachedHash`](struct@crate::CachedHash) and the internal hash is computed in the
+/// same way so see the [`CachedHash`](struct@crate::CachedHash) documentation for
+/// details.
+///
+/// ```compile_fail
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<cachedhash::LocalCachedHash<String>>();
+/// ```
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct LocalCachedHash<
+    T: ?Sized,
+    BH: BuildHasher = BuildHasherDefault<DefaultHasher>,
+    P: SentinelPolicy = BumpZero,
+> {
+    hash: P::LocalSlot,
+    build_hasher: BH,
+    value: T,
+}
+
+/// A single-threaded version of [`CachedHash`](struct@crate::CachedHash).
+///
+/// This is the `no_std` version of [`LocalCachedHash`] which requires the `BH`
+/// [`BuildHasher`] to be always specified.
+#[cfg(not(feature = "std"))]
+#[derive(Debug)]
+pub struct LocalCachedHash<T: ?Sized, BH: BuildHasher, P: SentinelPolicy = BumpZero> {
+    hash: P::LocalSlot,
+    build_hasher: BH,
+    value: T,
+}
+
+wrapper::impl_cached_hash!(
+    LocalCachedHash,
+    /// A guard giving mutable access to the value stored in a [`LocalCachedHash`].
+    ///
+    /// Created by [`LocalCachedHash::modify`] and [`LocalCachedHash::modify_with_policy`].
+    /// See [`CachedHashGuard`](crate::CachedHashGuard) for details.
+    LocalCachedHashGuard,
+    LocalSlot,
+    BumpZero,
+);
+
+#[cfg(test)]
+wrapper::cached_hash_tests!(LocalCachedHash, LocalCachedHashGuard);
@@ -0,0 +1,368 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::hash::{BuildHasher, BuildHasherDefault, Hash};
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+use crate::{BuildPassThroughHasher, CachedHash};
+
+/// A memoization cache for functions whose arguments are [`CachedHash`](struct@CachedHash) values.
+///
+/// Looking up a key uses its cached internal hash directly via
+/// [`PassThroughHasher`](crate::PassThroughHasher), so once the key is hashed
+/// a cache hit does no hashing at all, only the equality check of the found
+/// entry:
+///
+/// ```
+/// use cachedhash::{CachedHash, Memo};
+///
+/// let mut memo = Memo::new();
+/// let input = CachedHash::new(vec![1_u64; 10_000]);
+/// let sum = *memo.get_or_insert_with(&input, |input| input.iter().sum::<u64>());
+/// // a hit neither hashes `input` again nor calls the closure
+/// assert_eq!(*memo.get_or_insert_with(&input, |_| unreachable!()), sum);
+/// ```
+///
+/// The keys are cloned into the cache only when a new entry is inserted, and
+/// the clone keeps the cached hash.
+///
+/// A [`Memo`] created by [`Memo::new`] grows without bounds.
+/// [`Memo::with_lru_limit`] creates one holding at most the given number of
+/// entries which evicts the least recently used entry when it is full. Only
+/// [`Memo::get`], [`Memo::get_or_insert_with`] and [`Memo::insert`] count as a
+/// use, [`Memo::peek`] and [`Memo::contains_key`] do not.
+///
+/// Like with [`CachedHashMap`](crate::CachedHashMap) the keys must compute their
+/// internal hash in the same way, which is always the case for zero-sized
+/// [`BuildHasher`]s such as the default [`BuildHasherDefault`]. With a
+/// [`BuildHasher`] with state create all the keys with clones of the same one.
+///
+/// Only available with the `std` feature.
+pub struct Memo<K, V, BH: BuildHasher = BuildHasherDefault<DefaultHasher>> {
+    indices: HashMap<Arc<CachedHash<K, BH>>, usize, BuildPassThroughHasher>,
+    entries: Vec<MemoEntry<K, V, BH>>,
+    /// The indices of the entries by their last use, only kept with a limit.
+    recency: BTreeMap<u64, usize>,
+    clock: u64,
+    limit: Option<NonZeroUsize>,
+}
+
+/// An entry of a [`Memo`], the key is shared with the index.
+struct MemoEntry<K, V, BH: BuildHasher> {
+    key: Arc<CachedHash<K, BH>>,
+    value: V,
+    last_used: u64,
+}
+
+impl<K: Eq + Hash, V> Memo<K, V> {
+    /// Creates an empty unbounded [`Memo`] whose keys use [`DefaultHasher`].
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<K: Eq + Hash, V, BH: BuildHasher> Memo<K, V, BH> {
+    /// Creates an empty [`Memo`] which holds at most `limit` entries and
+    /// evicts the least recently used one when it is full.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is zero.
+    #[must_use]
+    pub fn with_lru_limit(limit: usize) -> Self {
+        let limit = NonZeroUsize::new(limit).expect("the limit must not be zero");
+        Self {
+            limit: Some(limit),
+            ..Self::default()
+        }
+    }
+
+    /// Returns the maximum number of entries, [`None`] if the [`Memo`] is unbounded.
+    #[inline]
+    #[must_use]
+    pub fn limit(&self) -> Option<usize> {
+        self.limit.map(NonZeroUsize::get)
+    }
+
+    /// Returns the number of entries.
+    #[inline]
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if there are no entries.
+    #[inline]
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Removes all the entries.
+    pub fn clear(&mut self) {
+        self.indices.clear();
+        self.entries.clear();
+        self.recency.clear();
+    }
+
+    /// Returns `true` if there is an entry for `key`, without marking it as used.
+    #[inline]
+    pub fn contains_key(&self, key: &CachedHash<K, BH>) -> bool {
+        self.indices.contains_key(key)
+    }
+
+    /// Returns the value for `key` without marking it as used.
+    #[inline]
+    pub fn peek(&self, key: &CachedHash<K, BH>) -> Option<&V> {
+        let &index = self.indices.get(key)?;
+        Some(&self.entries[index].value)
+    }
+
+    /// Returns the value for `key` and marks it as used.
+    #[inline]
+    pub fn get(&mut self, key: &CachedHash<K, BH>) -> Option<&V> {
+        let &index = self.indices.get(key)?;
+        self.touch(index);
+        Some(&self.entries[index].value)
+    }
+
+    /// Returns the value for `key`, computing it by `f` and inserting it
+    /// (evicting the least recently used entry if full) if there is none.
+    pub fn get_or_insert_with(&mut self, key: &CachedHash<K, BH>, f: impl FnOnce(&K) -> V) -> &V
+    where
+        K: Clone,
+        BH: Clone,
+    {
+        let index = if let Some(&index) = self.indices.get(key) {
+            self.touch(index);
+            index
+        } else {
+            let value = f(CachedHash::get(key));
+            // hash before cloning so that the caller's key caches the hash too
+            CachedHash::ensure_hashed(key);
+            self.push(Arc::new(key.clone()), value)
+        };
+        &self.entries[index].value
+    }
+
+    /// Inserts `value` for `key` (evicting the least recently used entry if
+    /// full) and returns the previous value for `key` if there was one.
+    pub fn insert(&mut self, key: CachedHash<K, BH>, value: V) -> Option<V> {
+        if let Some(&index) = self.indices.get(&key) {
+            self.touch(index);
+            return Some(std::mem::replace(&mut self.entries[index].value, value));
+        }
+        self.push(Arc::new(key), value);
+        None
+    }
+
+    /// Removes the entry for `key` and returns its value if there was one.
+    pub fn remove(&mut self, key: &CachedHash<K, BH>) -> Option<V> {
+        let &index = self.indices.get(key)?;
+        Some(self.remove_index(index))
+    }
+
+    /// Marks the entry at `index` as the most recently used one.
+    fn touch(&mut self, index: usize) {
+        if self.limit.is_none() {
+            return;
+        }
+        let entry = &mut self.entries[index];
+        self.recency.remove(&entry.last_used);
+        self.clock += 1;
+        entry.last_used = self.clock;
+        self.recency.insert(self.clock, index);
+    }
+
+    /// Adds a new entry, evicting the least recently used one first if full,
+    /// and returns its index.
+    fn push(&mut self, key: Arc<CachedHash<K, BH>>, value: V) -> usize {
+        if let Some(limit) = self.limit {
+            if self.entries.len() >= limit.get() {
+                let (_, oldest) = self.recency.pop_first().expect("the memo is full");
+                self.remove_index(oldest);
+            }
+            self.clock += 1;
+            self.recency.insert(self.clock, self.entries.len());
+        }
+        let index = self.entries.len();
+        self.indices.insert(Arc::clone(&key), index);
+        self.entries.push(MemoEntry {
+            key,
+            value,
+            last_used: self.clock,
+        });
+        index
+    }
+
+    /// Removes the entry at `index`, moving the last entry in its place.
+    fn remove_index(&mut self, index: usize) -> V {
+        let entry = self.entries.swap_remove(index);
+        self.indices.remove(&*entry.key);
+        if self.limit.is_some() {
+            self.recency.remove(&entry.last_used);
+        }
+        if let Some(moved) = self.entries.get(index) {
+            *self
+                .indices
+                .get_mut(&*moved.key)
+                .expect("every entry is indexed") = index;
+            if self.limit.is_some() {
+                self.recency.insert(moved.last_used, index);
+            }
+        }
+        entry.value
+    }
+}
+
+impl<K: Eq + Hash, V, BH: BuildHasher> Default for Memo<K, V, BH> {
+    fn default() -> Self {
+        Self {
+            indices: HashMap::default(),
+            entries: Vec::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            limit: None,
+        }
+    }
+}
+
+impl<K: Hash + Debug, V: Debug, BH: BuildHasher> Debug for Memo<K, V, BH> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map()
+            .entries(
+                self.entries
+                    .iter()
+                    .map(|entry| (CachedHash::get(&entry.key), &entry.value)),
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::hash::Hasher;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use oorandom::Rand64;
+
+    use super::*;
+
+    #[test]
+    fn hit_does_no_hashing() {
+        static HASHED: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(Clone, PartialEq, Eq)]
+        struct Key(u64);
+
+        impl Hash for Key {
+            fn hash<H: Hasher>(&self, state: &mut H) {
+                HASHED.fetch_add(1, Ordering::Relaxed);
+                self.0.hash(state);
+            }
+        }
+
+        let mut memo = Memo::new();
+        let key = CachedHash::new(Key(1));
+        let mut calls = 0;
+        for _ in 0..10 {
+            let value = memo.get_or_insert_with(&key, |key| {
+                calls += 1;
+                key.0 * 2
+            });
+            assert_eq!(*value, 2);
+        }
+        assert_eq!(calls, 1);
+        assert_eq!(memo.peek(&key), Some(&2));
+        assert_eq!(memo.get(&key), Some(&2));
+        #[cfg(not(feature = "verify"))] // verify rehashes on every use
+        assert_eq!(HASHED.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn insert_and_remove() {
+        let mut memo = Memo::new();
+        assert_eq!(memo.insert(CachedHash::new("foo"), 1), None);
+        assert_eq!(memo.insert(CachedHash::new("bar"), 2), None);
+        assert_eq!(memo.insert(CachedHash::new("foo"), 3), Some(1));
+        assert_eq!(memo.len(), 2);
+        assert_eq!(memo.remove(&CachedHash::new("foo")), Some(3));
+        assert_eq!(memo.remove(&CachedHash::new("foo")), None);
+        assert!(!memo.contains_key(&CachedHash::new("foo")));
+        assert_eq!(memo.peek(&CachedHash::new("bar")), Some(&2));
+        assert_eq!(memo.limit(), None);
+        memo.clear();
+        assert!(memo.is_empty());
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut memo = Memo::with_lru_limit(2);
+        let foo = CachedHash::new("foo");
+        let bar = CachedHash::new("bar");
+        let baz = CachedHash::new("baz");
+        memo.get_or_insert_with(&foo, |_| 1);
+        memo.get_or_insert_with(&bar, |_| 2);
+        // peeking does not count as a use
+        assert_eq!(memo.peek(&foo), Some(&1));
+        assert_eq!(memo.get(&foo), Some(&1));
+        memo.get_or_insert_with(&baz, |_| 3);
+        assert_eq!(memo.len(), 2);
+        assert!(!memo.contains_key(&bar));
+        assert_eq!(memo.peek(&foo), Some(&1));
+        assert_eq!(memo.peek(&baz), Some(&3));
+        assert_eq!(memo.limit(), Some(2));
+    }
+
+    #[test]
+    fn random_operations_match_a_model() {
+        for seed in 0..10 {
+            let mut rng = Rand64::new(seed);
+            let mut memo = Memo::with_lru_limit(8);
+            // most recently used last
+            let mut model = VecDeque::<(u64, u64)>::new();
+            for _ in 0..2000 {
+                let key = rng.rand_range(0..16);
+                let cached_key = CachedHash::new(key);
+                let position = model.iter().position(|&(k, _)| k == key);
+                match rng.rand_range(0..4) {
+                    0 | 1 => {
+                        let value = *memo.get_or_insert_with(&cached_key, |&key| key * 10);
+                        let entry = position
+                            .map_or((key, key * 10), |position| model.remove(position).unwrap());
+                        assert_eq!(value, entry.1);
+                        model.push_back(entry);
+                    }
+                    2 => {
+                        let value = rng.rand_u64();
+                        let old = memo.insert(cached_key, value);
+                        let old_model = position.map(|position| model.remove(position).unwrap().1);
+                        assert_eq!(old, old_model);
+                        model.push_back((key, value));
+                    }
+                    _ => {
+                        let old = memo.remove(&cached_key);
+                        let old_model = position.map(|position| model.remove(position).unwrap().1);
+                        assert_eq!(old, old_model);
+                    }
+                }
+                if model.len() > 8 {
+                    model.pop_front();
+                }
+                assert_eq!(memo.len(), model.len());
+                for &(key, value) in &model {
+                    assert_eq!(memo.peek(&CachedHash::new(key)), Some(&value));
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "the limit must not be zero")]
+    fn zero_limit() {
+        let _ = Memo::<u8, u8>::with_lru_limit(0);
+    }
+}
@@ -0,0 +1,183 @@
+use core::hash::{BuildHasherDefault, Hasher};
+
+#[cfg(doc)]
+use crate::CachedHash;
+#[cfg(all(doc, feature = "std"))]
+use std::collections::HashMap;
+
+/// A [`Hasher`] that returns the single [`u64`] written into it as the hash.
+///
+/// [`CachedHash`](struct@CachedHash) implements [`Hash`](std::hash::Hash) by writing its cached
+/// internal hash into the hasher using [`Hasher::write_u64`]. Hashing that
+/// value again with a general purpose hasher (such as the `SipHash` used by
+/// [`HashMap`] by default) is unnecessary work. [`PassThroughHasher`] instead
+/// returns the written value as is so moving [`CachedHash`](struct@CachedHash) values between
+/// collections using it does no hashing work at all once the internal hash is
+/// cached.
+///
+/// The hasher expects exactly one call to [`Hasher::write_u64`] (or
+/// [`Hasher::write_u32`]) before [`Hasher::finish`]. In debug builds feeding it anything else panics. In
+/// release builds other writes, including further calls to [`Hasher::write_u64`],
+/// are folded into the hash so that the result is still a valid (albeit low
+/// quality) hash.
+///
+/// Only use this hasher for keys whose [`Hash`](std::hash::Hash)
+/// implementation writes a single well distributed [`u64`] or [`u32`], such as
+/// [`CachedHash`](struct@CachedHash), [`LocalCachedHash`](crate::LocalCachedHash) and
+/// [`CachedHash32`](crate::CachedHash32).
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PassThroughHasher {
+    hash: u64,
+    written: bool,
+}
+
+impl Hasher for PassThroughHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        debug_assert!(
+            bytes.is_empty(),
+            "PassThroughHasher only accepts a single write_u64, got {} bytes",
+            bytes.len()
+        );
+        for &byte in bytes {
+            self.hash = self.hash.rotate_left(8) ^ u64::from(byte);
+        }
+    }
+
+    #[inline]
+    fn write_u64(&mut self, i: u64) {
+        debug_assert!(
+            !self.written,
+            "PassThroughHasher only accepts a single write_u64, got more"
+        );
+        self.hash = self.hash.rotate_left(32) ^ i;
+        self.written = true;
+    }
+
+    /// Accepts the 32-bit internal hash written by
+    /// [`CachedHash32`](crate::CachedHash32).
+    ///
+    /// The value is spread over all 64 bits by multiplying it with an odd
+    /// constant as hash tables such as [`HashMap`] also use the top bits.
+    #[inline]
+    fn write_u32(&mut self, i: u32) {
+        self.write_u64(u64::from(i).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    }
+
+    #[inline]
+    fn finish(&self) -> u64 {
+        debug_assert!(
+            self.written,
+            "PassThroughHasher only accepts a single write_u64, got none"
+        );
+        self.hash
+    }
+}
+
+/// A [`BuildHasher`](std::hash::BuildHasher) creating [`PassThroughHasher`]s.
+pub type BuildPassThroughHasher = BuildHasherDefault<PassThroughHasher>;
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::HashMap;
+    use std::hash::{BuildHasher, Hash, Hasher};
+
+    use super::*;
+    use crate::{CachedHash, CachedHashSet};
+
+    #[test]
+    fn passes_u64_through() {
+        let mut hasher = PassThroughHasher::default();
+        hasher.write_u64(42);
+        assert_eq!(hasher.finish(), 42);
+    }
+
+    #[test]
+    fn spreads_u32() {
+        let mut hasher = PassThroughHasher::default();
+        hasher.write_u32(42);
+        assert_ne!(hasher.finish() >> 32, 0);
+        let mut other = PassThroughHasher::default();
+        other.write_u32(43);
+        assert_ne!(hasher.finish(), other.finish());
+    }
+
+    #[test]
+    fn hash_is_internal_hash() {
+        let foo = CachedHash::new("foo".to_string());
+        let internal_hash = BuildHasherDefault::<DefaultHasher>::default().hash_one("foo");
+        assert_eq!(
+            BuildPassThroughHasher::default().hash_one(&foo),
+            internal_hash
+        );
+    }
+
+    #[test]
+    #[should_panic = "PassThroughHasher only accepts a single write_u64"]
+    #[cfg(debug_assertions)]
+    fn panics_on_other_writes() {
+        let mut hasher = PassThroughHasher::default();
+        "foo".hash(&mut hasher);
+    }
+
+    #[test]
+    #[should_panic = "PassThroughHasher only accepts a single write_u64"]
+    #[cfg(debug_assertions)]
+    fn panics_on_multiple_writes() {
+        let mut hasher = PassThroughHasher::default();
+        hasher.write_u64(1);
+        hasher.write_u64(2);
+    }
+
+    #[test]
+    #[cfg(not(debug_assertions))]
+    fn folds_multiple_writes() {
+        let fold = |values: &[u64]| {
+            let mut hasher = PassThroughHasher::default();
+            for &value in values {
+                hasher.write_u64(value);
+            }
+            hasher.finish()
+        };
+        assert_eq!(fold(&[1]), 1);
+        assert_ne!(fold(&[1, 2]), fold(&[2]));
+        assert_ne!(fold(&[1, 2]), fold(&[2, 1]));
+    }
+
+    #[test]
+    #[cfg(not(debug_assertions))]
+    fn folds_writes_before_write_u64() {
+        let fold = |tag: u8, value: u64| {
+            let mut hasher = PassThroughHasher::default();
+            hasher.write_u8(tag);
+            hasher.write_u64(value);
+            hasher.finish()
+        };
+        assert_ne!(fold(1, 42), fold(2, 42));
+        assert_ne!(fold(1, 42), 42);
+    }
+
+    #[test]
+    #[should_panic = "PassThroughHasher only accepts a single write_u64"]
+    #[cfg(debug_assertions)]
+    fn panics_on_no_writes() {
+        let _ = PassThroughHasher::default().finish();
+    }
+
+    #[test]
+    #[allow(clippy::mutable_key_type)]
+    fn map_and_set() {
+        let mut map: HashMap<_, _, BuildPassThroughHasher> = HashMap::default();
+        map.insert(CachedHash::new("foo".to_string()), 1);
+        map.insert(CachedHash::new("bar".to_string()), 2);
+        assert_eq!(map.get(&CachedHash::new("foo".to_string())), Some(&1));
+        assert_eq!(map.get(&CachedHash::new("baz".to_string())), None);
+
+        let mut set = CachedHashSet::default();
+        for (key, _) in map.drain() {
+            set.insert(key);
+        }
+        assert!(set.contains(&CachedHash::new("bar".to_string())));
+        assert_eq!(set.len(), 2);
+    }
+}
@@ -0,0 +1,396 @@
+use core::cell::Cell;
+use core::fmt::Debug;
+use core::hash::Hasher;
+use core::num::{NonZeroU32, NonZeroU64};
+
+use crate::atomic::{AtomicOptionNonZeroU32, AtomicOptionNonZeroU64, AtomicOptionU64};
+
+/// Chooses how [`CachedHash`](struct@crate::CachedHash) and
+/// [`LocalCachedHash`](crate::LocalCachedHash) represent a hash that has not
+/// been computed yet.
+///
+/// Storing both the full range of [`u64`] hashes and "no hash" needs 65 bits.
+/// The policies either reserve one hash value as a sentinel, which creates an
+/// artificial collision, or store the extra bit separately, which costs space.
+/// [`Fold32`], used by [`CachedHash32`](crate::CachedHash32), instead keeps only
+/// 32 bits of the hash.
+///
+/// | Policy         | Internal hash  | Collision                    | Extra size |
+/// |----------------|----------------|------------------------------|------------|
+/// | [`BumpZero`]   | [`NonZeroU64`] | 0 and 1                      | 8 bytes    |
+/// | [`RemixZero`]  | [`NonZeroU64`] | 0 and [`RemixZero::REMIXED`] | 8 bytes    |
+/// | [`NoSentinel`] | [`u64`]        | none                         | 16 bytes   |
+/// | [`Fold32`]     | [`NonZeroU32`] | all with equal folded hashes | 4 bytes    |
+///
+/// The sizes are for 64-bit targets. [`Fold32`] only saves space for values
+/// aligned to at most 4 bytes, otherwise it is padded to 8 bytes as well.
+/// Replacing the sentinel costs about the same for [`BumpZero`] and
+/// [`RemixZero`] while [`NoSentinel`] can be slightly slower because of the
+/// larger entries. The `CachedPassThrough*` benchmarks of
+/// `cargo bench --bench base` compare them on your machine.
+///
+/// This trait is sealed and cannot be implemented outside of this crate.
+pub trait SentinelPolicy: sealed::Sealed + Debug + Default + Clone + Copy {
+    /// The type of the internal hash as returned by
+    /// [`CachedHash::internal_hash`](crate::CachedHash::internal_hash).
+    type Hash: Debug + Copy + Eq + InternalHash + Send + Sync;
+    #[doc(hidden)]
+    type AtomicSlot: Slot<Self::Hash> + Send + Sync;
+    #[doc(hidden)]
+    type LocalSlot: Slot<Self::Hash>;
+
+    /// Turns a hash computed by a [`Hasher`](core::hash::Hasher) into the
+    /// internal hash.
+    fn from_raw(hash: u64) -> Self::Hash;
+}
+
+/// The default [`SentinelPolicy`] which reserves 0 as the sentinel and bumps
+/// an internal hash of 0 up to 1.
+///
+/// This is cheap and for general purpose hashers the collision is as likely as
+/// any other. However, identity hashers such as
+/// [`NoHashHasher`](https://docs.rs/nohash-hasher) make keys 0 and 1 collide
+/// systematically. Use [`RemixZero`] or [`NoSentinel`] for those.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BumpZero;
+
+/// A [`SentinelPolicy`] which reserves 0 as the sentinel and replaces an
+/// internal hash of 0 by the fixed odd constant [`RemixZero::REMIXED`].
+///
+/// There is still one collision but it is between 0 and a value unlikely to
+/// show up as a key, so it fits identity hashers with small integer keys.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RemixZero;
+
+impl RemixZero {
+    /// The internal hash used instead of 0.
+    pub const REMIXED: NonZeroU64 = match NonZeroU64::new(0x9e37_79b9_7f4a_7c15) {
+        Some(hash) => hash,
+        None => unreachable!(),
+    };
+}
+
+/// A [`SentinelPolicy`] which stores whether the hash is computed separately
+/// from the hash itself so that all 2<sup>64</sup> internal hashes are
+/// representable and there is no collision at all.
+///
+/// The price is size: the flag and its padding usually add 8 bytes on top of
+/// the other policies.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NoSentinel;
+
+/// A [`SentinelPolicy`] which folds the internal hash into 32 bits by xoring
+/// its two halves, reserves 0 as the sentinel and bumps a folded hash of 0 up
+/// to 1.
+///
+/// This is the policy of [`CachedHash32`](crate::CachedHash32). The cached hash
+/// is written into outer hashers using [`Hasher::write_u32`] so identity hashers
+/// such as [`PassThroughHasher`](crate::PassThroughHasher) still work.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fold32;
+
+impl SentinelPolicy for BumpZero {
+    type Hash = NonZeroU64;
+    type AtomicSlot = AtomicOptionNonZeroU64;
+    type LocalSlot = Cell<Option<NonZeroU64>>;
+
+    #[inline]
+    fn from_raw(hash: u64) -> Self::Hash {
+        NonZeroU64::new(hash).unwrap_or(NonZeroU64::MIN)
+    }
+}
+
+impl SentinelPolicy for RemixZero {
+    type Hash = NonZeroU64;
+    type AtomicSlot = AtomicOptionNonZeroU64;
+    type LocalSlot = Cell<Option<NonZeroU64>>;
+
+    #[inline]
+    fn from_raw(hash: u64) -> Self::Hash {
+        NonZeroU64::new(hash).unwrap_or(Self::REMIXED)
+    }
+}
+
+impl SentinelPolicy for NoSentinel {
+    type Hash = u64;
+    type AtomicSlot = AtomicOptionU64;
+    type LocalSlot = Cell<Option<u64>>;
+
+    #[inline]
+    fn from_raw(hash: u64) -> Self::Hash {
+        hash
+    }
+}
+
+impl SentinelPolicy for Fold32 {
+    type Hash = NonZeroU32;
+    type AtomicSlot = AtomicOptionNonZeroU32;
+    type LocalSlot = Cell<Option<NonZeroU32>>;
+
+    #[inline]
+    fn from_raw(hash: u64) -> Self::Hash {
+        #[allow(clippy::cast_possible_truncation)]
+        let folded = (hash ^ (hash >> 32)) as u32;
+        NonZeroU32::new(folded).unwrap_or(NonZeroU32::MIN)
+    }
+}
+
+/// An internal hash of a [`SentinelPolicy`].
+pub trait InternalHash: Copy {
+    /// Returns the hash widened to 64 bits.
+    fn to_u64(self) -> u64;
+
+    /// Writes the hash into `state` as the [`Hash`](core::hash::Hash)
+    /// implementations of the wrappers do.
+    #[inline]
+    fn write_to<H: Hasher>(self, state: &mut H) {
+        state.write_u64(self.to_u64());
+    }
+}
+
+impl InternalHash for u64 {
+    #[inline]
+    fn to_u64(self) -> u64 {
+        self
+    }
+}
+
+impl InternalHash for NonZeroU64 {
+    #[inline]
+    fn to_u64(self) -> u64 {
+        self.get()
+    }
+}
+
+impl InternalHash for NonZeroU32 {
+    #[inline]
+    fn to_u64(self) -> u64 {
+        u64::from(self.get())
+    }
+
+    #[inline]
+    fn write_to<H: Hasher>(self, state: &mut H) {
+        state.write_u32(self.get());
+    }
+}
+
+/// Storage for a maybe-hash.
+pub trait Slot<H: InternalHash>: Debug + Clone {
+    /// A slot with no hash.
+    #[allow(clippy::declare_interior_mutable_const)] // only ever used by value
+    const NONE: Self;
+
+    fn new_some(hash: H) -> Self;
+
+    fn get(&self) -> Option<H>;
+
+    fn set(&self, hash: Option<H>);
+}
+
+impl Slot<NonZeroU64> for AtomicOptionNonZeroU64 {
+    #[allow(clippy::declare_interior_mutable_const)] // only ever used by value
+    const NONE: Self = Self::new_none();
+
+    #[inline]
+    fn new_some(hash: NonZeroU64) -> Self {
+        Self::new_some(hash)
+    }
+
+    #[inline]
+    fn get(&self) -> Option<NonZeroU64> {
+        self.get()
+    }
+
+    #[inline]
+    fn set(&self, hash: Option<NonZeroU64>) {
+        self.set(hash);
+    }
+}
+
+impl Slot<NonZeroU32> for AtomicOptionNonZeroU32 {
+    #[allow(clippy::declare_interior_mutable_const)] // only ever used by value
+    const NONE: Self = Self::new_none();
+
+    #[inline]
+    fn new_some(hash: NonZeroU32) -> Self {
+        Self::new_some(hash)
+    }
+
+    #[inline]
+    fn get(&self) -> Option<NonZeroU32> {
+        self.get()
+    }
+
+    #[inline]
+    fn set(&self, hash: Option<NonZeroU32>) {
+        self.set(hash);
+    }
+}
+
+impl Slot<u64> for AtomicOptionU64 {
+    #[allow(clippy::declare_interior_mutable_const)] // only ever used by value
+    const NONE: Self = Self::new_none();
+
+    #[inline]
+    fn new_some(hash: u64) -> Self {
+        Self::new_some(hash)
+    }
+
+    #[inline]
+    fn get(&self) -> Option<u64> {
+        self.get()
+    }
+
+    #[inline]
+    fn set(&self, hash: Option<u64>) {
+        self.set(hash);
+    }
+}
+
+impl<H: Debug + InternalHash> Slot<H> for Cell<Option<H>> {
+    #[allow(clippy::declare_interior_mutable_const)] // only ever used by value
+    const NONE: Self = Self::new(None);
+
+    #[inline]
+    fn new_some(hash: H) -> Self {
+        Self::new(Some(hash))
+    }
+
+    #[inline]
+    fn get(&self) -> Option<H> {
+        self.get()
+    }
+
+    #[inline]
+    fn set(&self, hash: Option<H>) {
+        self.set(hash);
+    }
+}
+
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for super::BumpZero {}
+    impl Sealed for super::RemixZero {}
+    impl Sealed for super::NoSentinel {}
+    impl Sealed for super::Fold32 {}
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::collections::HashSet;
+    use std::hash::{BuildHasher, BuildHasherDefault};
+    use std::mem::size_of;
+
+    use nohash_hasher::NoHashHasher;
+
+    use super::*;
+    use crate::{BuildPassThroughHasher, CachedHash, LocalCachedHash, RehashPolicy};
+
+    type NoHashBuild = BuildHasherDefault<NoHashHasher<u64>>;
+
+    fn cached<P: SentinelPolicy>(value: u64) -> CachedHash<u64, NoHashBuild, P> {
+        CachedHash::new_with_sentinel(value, NoHashBuild::default(), P::default())
+    }
+
+    #[test]
+    fn bump_zero_collides_with_one() {
+        assert_eq!(CachedHash::internal_hash(&cached::<BumpZero>(0)).get(), 1);
+        assert_eq!(CachedHash::internal_hash(&cached::<BumpZero>(1)).get(), 1);
+    }
+
+    #[test]
+    fn remix_zero_moves_the_collision() {
+        assert_eq!(
+            CachedHash::internal_hash(&cached::<RemixZero>(0)),
+            RemixZero::REMIXED
+        );
+        assert_eq!(CachedHash::internal_hash(&cached::<RemixZero>(1)).get(), 1);
+        assert_eq!(
+            CachedHash::internal_hash(&cached::<RemixZero>(RemixZero::REMIXED.get())),
+            RemixZero::REMIXED
+        );
+    }
+
+    #[test]
+    fn no_sentinel_has_no_collision() {
+        let mut zero = cached::<NoSentinel>(0);
+        assert_eq!(CachedHash::cached_hash(&zero), None);
+        assert_eq!(CachedHash::internal_hash(&zero), 0);
+        assert_eq!(CachedHash::cached_hash(&zero), Some(0));
+        assert_eq!(CachedHash::cached_hash(&zero.clone()), Some(0));
+        CachedHash::invalidate_hash(&mut zero);
+        assert_eq!(CachedHash::cached_hash(&zero), None);
+        CachedHash::update(&mut zero, |value| *value = 1);
+        assert_eq!(CachedHash::cached_hash(&zero), Some(1));
+
+        let build = BuildPassThroughHasher::default();
+        assert_ne!(
+            build.hash_one(cached::<NoSentinel>(0)),
+            build.hash_one(cached::<NoSentinel>(1))
+        );
+        assert_eq!(
+            build.hash_one(cached::<BumpZero>(0)),
+            build.hash_one(cached::<BumpZero>(1))
+        );
+    }
+
+    #[test]
+    fn fold_32_bumps_zero() {
+        let folded = |value| CachedHash::internal_hash(&cached::<Fold32>(value)).get();
+        assert_eq!(folded(0), 1);
+        assert_eq!(folded(1), 1);
+        assert_eq!(folded((1 << 32) | 1), 1);
+        assert_eq!(folded((1 << 32) | 3), 2);
+    }
+
+    #[test]
+    fn no_sentinel_parts() {
+        let foo = CachedHash::from_parts_with_sentinel(0, 0, NoHashBuild::default(), NoSentinel);
+        assert!(CachedHash::verify_hash(&foo));
+        let (value, hash, _) = CachedHash::into_parts(foo);
+        assert_eq!((value, hash), (0, Some(0)));
+    }
+
+    #[test]
+    fn local_policies() {
+        let zero: LocalCachedHash<u64, NoHashBuild, NoSentinel> =
+            LocalCachedHash::new_with_sentinel(0, NoHashBuild::default(), NoSentinel);
+        assert_eq!(LocalCachedHash::internal_hash(&zero), 0);
+        let mut remixed: LocalCachedHash<u64, NoHashBuild, RemixZero> = 0.into();
+        assert_eq!(LocalCachedHash::internal_hash(&remixed), RemixZero::REMIXED);
+        *LocalCachedHash::modify_with_policy(&mut remixed, RehashPolicy::Lazy) = 2;
+        assert_eq!(LocalCachedHash::cached_hash(&remixed), None);
+    }
+
+    #[test]
+    #[allow(clippy::mutable_key_type)]
+    fn no_sentinel_in_hash_set() {
+        let set: HashSet<_, BuildPassThroughHasher> = (0..100).map(cached::<NoSentinel>).collect();
+        assert_eq!(set.len(), 100);
+        assert!(set.contains(&cached::<NoSentinel>(0)));
+        assert!(!set.contains(&cached::<NoSentinel>(100)));
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn sizes() {
+        assert_eq!(size_of::<CachedHash<String>>(), size_of::<String>() + 8);
+        assert_eq!(
+            size_of::<CachedHash<String, NoHashBuild, RemixZero>>(),
+            size_of::<String>() + 8
+        );
+        assert_eq!(
+            size_of::<CachedHash<String, NoHashBuild, NoSentinel>>(),
+            size_of::<String>() + 16
+        );
+        assert_eq!(
+            size_of::<LocalCachedHash<String, NoHashBuild, NoSentinel>>(),
+            size_of::<String>() + 16
+        );
+        assert_eq!(
+            size_of::<CachedHash<u32, NoHashBuild, Fold32>>(),
+            size_of::<u32>() + 4
+        );
+    }
+}
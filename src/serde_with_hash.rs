@@ -0,0 +1,236 @@
+//! Serialization of [`CachedHash`](struct@CachedHash) together with its cached internal hash.
+//!
+//! By default [`CachedHash`](struct@CachedHash) is serialized transparently, only the stored value
+//! is written and the hash gets recomputed when needed after deserialization.
+//! Use this module with `#[serde(with = "cachedhash::serde_with_hash")]` to also
+//! write the cached internal hash (if any) and restore it on load, avoiding
+//! the recomputation.
+//!
+//! ```
+//! # #[cfg(feature = "std")] {
+//! # use cachedhash::CachedHash;
+//! # use serde::{Deserialize, Serialize};
+//! #[derive(Serialize, Deserialize)]
+//! struct Snapshot {
+//!     #[serde(with = "cachedhash::serde_with_hash")]
+//!     config: CachedHash<String>,
+//! }
+//! # }
+//! ```
+//!
+//! This is only correct if the internal hash computed by `BH` is the same in
+//! the process that serialized the value and in the one deserializing it. It
+//! must not be used with randomly seeded hashers such as
+//! [`RandomState`](std::collections::hash_map::RandomState). Note that the
+//! algorithm of [`DefaultHasher`](std::collections::hash_map::DefaultHasher) is
+//! not guaranteed to stay the same between Rust releases either, use
+//! [`StableCachedHash`](crate::StableCachedHash) when the hash is persisted.
+//! The restored hash is not checked, use [`CachedHash::verify_hash`] if the
+//! input is not trusted.
+//!
+//! Only available with the `serde` feature.
+
+use core::hash::BuildHasher;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{CachedHash, SentinelPolicy};
+
+#[derive(Serialize)]
+struct WithHashRef<'a, T, H> {
+    value: &'a T,
+    hash: Option<H>,
+}
+
+#[derive(Deserialize)]
+struct WithHash<T, H> {
+    value: T,
+    hash: Option<H>,
+}
+
+/// Serializes the stored value together with the cached internal hash.
+///
+/// # Errors
+///
+/// Returns an error if the value fails to serialize.
+pub fn serialize<T, BH, P, S>(this: &CachedHash<T, BH, P>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Serialize,
+    BH: BuildHasher,
+    P: SentinelPolicy,
+    P::Hash: Serialize,
+    S: Serializer,
+{
+    WithHashRef {
+        value: CachedHash::get(this),
+        hash: CachedHash::cached_hash(this),
+    }
+    .serialize(serializer)
+}
+
+/// Deserializes the stored value and restores the cached internal hash
+/// without recomputing it.
+///
+/// # Errors
+///
+/// Returns an error if the value or the hash fails to deserialize.
+pub fn deserialize<'de, T, BH, P, D>(deserializer: D) -> Result<CachedHash<T, BH, P>, D::Error>
+where
+    T: Deserialize<'de>,
+    BH: BuildHasher + Default,
+    P: SentinelPolicy,
+    P::Hash: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    let WithHash { value, hash } = WithHash::deserialize(deserializer)?;
+    Ok(match hash {
+        Some(hash) => {
+            CachedHash::from_parts_with_sentinel(value, hash, BH::default(), P::default())
+        }
+        None => CachedHash::new_with_sentinel(value, BH::default(), P::default()),
+    })
+}
+
+impl<T: Serialize, BH: BuildHasher, P: SentinelPolicy> Serialize for CachedHash<T, BH, P> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Self::get(self).serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>, BH: BuildHasher + Default, P: SentinelPolicy> Deserialize<'de>
+    for CachedHash<T, BH, P>
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer)
+            .map(|value| Self::new_with_sentinel(value, BH::default(), P::default()))
+    }
+}
+
+impl<T: Serialize, BH: BuildHasher, P: SentinelPolicy> Serialize
+    for crate::LocalCachedHash<T, BH, P>
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Self::get(self).serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>, BH: BuildHasher + Default, P: SentinelPolicy> Deserialize<'de>
+    for crate::LocalCachedHash<T, BH, P>
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer)
+            .map(|value| Self::new_with_sentinel(value, BH::default(), P::default()))
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::collections::hash_map::RandomState;
+    use std::hash::BuildHasherDefault;
+
+    use nohash_hasher::NoHashHasher;
+    use serde::{Deserialize, Serialize};
+
+    use crate::{CachedHash, LocalCachedHash, NoSentinel};
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Snapshot {
+        #[serde(with = "crate::serde_with_hash")]
+        with_hash: CachedHash<String>,
+        transparent: CachedHash<Vec<u32>>,
+    }
+
+    #[test]
+    fn transparent_json() {
+        let foo = CachedHash::new_hashed(vec![1, 2, 3]);
+        let json = serde_json::to_string(&foo).unwrap();
+        assert_eq!(json, "[1,2,3]");
+        let bar: CachedHash<Vec<u32>> = serde_json::from_str(&json).unwrap();
+        assert_eq!(CachedHash::cached_hash(&bar), None);
+        assert_eq!(foo, bar);
+
+        let local: LocalCachedHash<Vec<u32>> = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&local).unwrap(), json);
+    }
+
+    #[test]
+    fn with_hash_json() {
+        let snapshot = Snapshot {
+            with_hash: CachedHash::new_hashed("foo".to_string()),
+            transparent: CachedHash::new_hashed(vec![1]),
+        };
+        let hash = CachedHash::cached_hash(&snapshot.with_hash).unwrap();
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert_eq!(
+            json,
+            format!(r#"{{"with_hash":{{"value":"foo","hash":{hash}}},"transparent":[1]}}"#)
+        );
+
+        let loaded: Snapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(CachedHash::cached_hash(&loaded.with_hash), Some(hash));
+        assert!(CachedHash::verify_hash(&loaded.with_hash));
+        assert_eq!(CachedHash::cached_hash(&loaded.transparent), None);
+    }
+
+    #[test]
+    fn with_hash_json_without_cached_hash() {
+        let json = r#"{"with_hash":{"value":"foo","hash":null},"transparent":[]}"#;
+        let loaded: Snapshot = serde_json::from_str(json).unwrap();
+        assert_eq!(CachedHash::cached_hash(&loaded.with_hash), None);
+        assert_eq!(*loaded.with_hash, "foo");
+    }
+
+    #[test]
+    fn with_hash_bincode() {
+        let snapshot = Snapshot {
+            with_hash: CachedHash::new_hashed("foo".to_string()),
+            transparent: CachedHash::new(vec![1, 2]),
+        };
+        let bytes = bincode::serialize(&snapshot).unwrap();
+        let loaded: Snapshot = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(
+            CachedHash::cached_hash(&loaded.with_hash),
+            CachedHash::cached_hash(&snapshot.with_hash)
+        );
+        assert!(CachedHash::verify_hash(&loaded.with_hash));
+        assert_eq!(loaded.transparent, snapshot.transparent);
+    }
+
+    #[test]
+    fn with_hash_no_sentinel() {
+        #[derive(Serialize, Deserialize)]
+        struct Zero {
+            #[serde(with = "crate::serde_with_hash")]
+            value: CachedHash<u64, BuildHasherDefault<NoHashHasher<u64>>, NoSentinel>,
+        }
+
+        let zero = Zero {
+            value: CachedHash::from_parts_with_sentinel(
+                0,
+                0,
+                BuildHasherDefault::default(),
+                NoSentinel,
+            ),
+        };
+        let json = serde_json::to_string(&zero).unwrap();
+        assert_eq!(json, r#"{"value":{"value":0,"hash":0}}"#);
+        let loaded: Zero = serde_json::from_str(&json).unwrap();
+        assert_eq!(CachedHash::cached_hash(&loaded.value), Some(0));
+    }
+
+    #[test]
+    fn with_hash_is_wrong_for_random_state() {
+        #[derive(Serialize, Deserialize)]
+        struct Random {
+            #[serde(with = "crate::serde_with_hash")]
+            value: CachedHash<String, RandomState>,
+        }
+
+        let random = Random {
+            value: CachedHash::new_hashed_with_build_hasher("foo".to_string(), RandomState::new()),
+        };
+        let json = serde_json::to_string(&random).unwrap();
+        let loaded: Random = serde_json::from_str(&json).unwrap();
+        assert!(!CachedHash::verify_hash(&loaded.value));
+    }
+}
@@ -0,0 +1,370 @@
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
+use std::ops::Deref;
+use std::sync::Arc;
+
+use crate::sentinel::{BumpZero, SentinelPolicy};
+use crate::CachedHash;
+
+/// A reference counted [`CachedHash`](struct@CachedHash) whose cached hash is shared between all
+/// its clones.
+///
+/// [`SharedCachedHash`] keeps the value together with its cached hash behind
+/// a single [`Arc`]. Cloning it only bumps the reference count and the first
+/// hash computed by any of the clones is visible to all of them. This fits
+/// immutable data shared between many owners, where cloning a [`CachedHash`](struct@CachedHash)
+/// would copy the value and cache the hash separately for every copy.
+///
+/// [`SharedCachedHash`] implements [`Deref`] but not
+/// [`DerefMut`](std::ops::DerefMut) as the value may be shared. Use
+/// [`SharedCachedHash::make_mut`] to modify it. Like [`Arc::make_mut`] this
+/// clones the value if other clones exist, detaching it from them, and
+/// invalidates the hash of the (now unique) value.
+///
+/// [`PartialEq`] always compares the values, as a value such as `f64::NAN`
+/// need not be equal to itself. For `T: Eq` use [`SharedCachedHash::fast_eq`]
+/// which treats two [`SharedCachedHash`]es pointing to the same allocation as
+/// equal without comparing the values.
+///
+/// Only available with the `std` feature.
+#[derive(Debug)]
+pub struct SharedCachedHash<
+    T,
+    BH: BuildHasher = BuildHasherDefault<DefaultHasher>,
+    P: SentinelPolicy = BumpZero,
+> {
+    inner: Arc<CachedHash<T, BH, P>>,
+}
+
+impl<T> SharedCachedHash<T> {
+    /// Creates a new [`SharedCachedHash`] with the given value using [`DefaultHasher`].
+    pub fn new(value: T) -> Self {
+        Self::from(CachedHash::new(value))
+    }
+}
+
+impl<T, H: Hasher + Default> SharedCachedHash<T, BuildHasherDefault<H>> {
+    /// Creates a new [`SharedCachedHash`] with the given value using a provided
+    /// hasher type implementing [`Default`].
+    pub fn new_with_hasher(value: T) -> Self {
+        Self::from(CachedHash::new_with_hasher(value))
+    }
+}
+
+impl<T, BH: BuildHasher> SharedCachedHash<T, BH> {
+    /// Creates a new [`SharedCachedHash`] with the given value and [`BuildHasher`].
+    pub fn new_with_build_hasher(value: T, build_hasher: BH) -> Self {
+        Self::from(CachedHash::new_with_build_hasher(value, build_hasher))
+    }
+}
+
+impl<T, BH: BuildHasher, P: SentinelPolicy> SharedCachedHash<T, BH, P> {
+    /// Explicitly returns an immutable reference to the stored value.
+    #[inline]
+    #[must_use]
+    pub fn get(this: &Self) -> &T {
+        CachedHash::get(&this.inner)
+    }
+
+    /// Returns the shared [`CachedHash`](struct@CachedHash).
+    #[inline]
+    #[must_use]
+    pub fn as_cached_hash(this: &Self) -> &CachedHash<T, BH, P> {
+        &this.inner
+    }
+
+    /// Returns the cached internal hash if it is currently cached by any of
+    /// the clones.
+    #[inline]
+    #[must_use]
+    pub fn cached_hash(this: &Self) -> Option<P::Hash> {
+        CachedHash::cached_hash(&this.inner)
+    }
+
+    /// Returns the internal hash, computing and caching it for all the clones
+    /// if necessary.
+    #[inline]
+    #[must_use]
+    pub fn internal_hash(this: &Self) -> P::Hash
+    where
+        T: Hash,
+    {
+        CachedHash::internal_hash(&this.inner)
+    }
+
+    /// Returns a mutable reference to the stored value if there are no other
+    /// clones, invalidating the cached hash. Returns [`None`] otherwise.
+    ///
+    /// See [`Arc::get_mut`].
+    #[inline]
+    #[must_use]
+    pub fn get_mut(this: &mut Self) -> Option<&mut T> {
+        Arc::get_mut(&mut this.inner).map(CachedHash::get_mut)
+    }
+
+    /// Returns a mutable reference to the stored value, cloning it first if
+    /// there are other clones, and invalidates the cached hash.
+    ///
+    /// See [`Arc::make_mut`]. The other clones keep the original value and
+    /// its cached hash.
+    #[inline]
+    #[must_use]
+    pub fn make_mut(this: &mut Self) -> &mut T
+    where
+        T: Clone,
+        BH: Clone,
+    {
+        CachedHash::get_mut(Arc::make_mut(&mut this.inner))
+    }
+
+    /// Returns `true` if both point to the same allocation.
+    ///
+    /// See [`Arc::ptr_eq`].
+    #[inline]
+    #[must_use]
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        Arc::ptr_eq(&this.inner, &other.inner)
+    }
+
+    /// Returns the inner [`CachedHash`](struct@CachedHash) if there are no other clones.
+    /// Otherwise returns the [`SharedCachedHash`] back.
+    ///
+    /// See [`Arc::try_unwrap`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `this` unchanged if there are other clones.
+    #[inline]
+    pub fn try_unwrap(this: Self) -> Result<CachedHash<T, BH, P>, Self> {
+        Arc::try_unwrap(this.inner).map_err(|inner| Self { inner })
+    }
+}
+
+impl<T, BH: BuildHasher, P: SentinelPolicy> Clone for SharedCachedHash<T, BH, P> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<T: Eq, BH: BuildHasher, P: SentinelPolicy> SharedCachedHash<T, BH, P> {
+    /// Returns `true` if both point to the same allocation and compares the
+    /// values otherwise.
+    ///
+    /// Unlike [`PartialEq`] this needs `T: Eq` as only then every value is
+    /// equal to itself.
+    #[inline]
+    #[must_use]
+    pub fn fast_eq(this: &Self, other: &Self) -> bool {
+        Self::ptr_eq(this, other) || this.inner == other.inner
+    }
+}
+
+impl<T: PartialEq, BH: BuildHasher, P: SentinelPolicy> PartialEq for SharedCachedHash<T, BH, P> {
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner
+    }
+}
+
+impl<T: Eq, BH: BuildHasher, P: SentinelPolicy> Eq for SharedCachedHash<T, BH, P> {}
+
+impl<T: PartialOrd, BH: BuildHasher, P: SentinelPolicy> PartialOrd for SharedCachedHash<T, BH, P> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.inner.partial_cmp(&other.inner)
+    }
+}
+
+impl<T: Ord, BH: BuildHasher, P: SentinelPolicy> Ord for SharedCachedHash<T, BH, P> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.inner.cmp(&other.inner)
+    }
+}
+
+impl<T: Hash, BH: BuildHasher, P: SentinelPolicy> Hash for SharedCachedHash<T, BH, P> {
+    fn hash<H2: Hasher>(&self, state: &mut H2) {
+        self.inner.hash(state);
+    }
+}
+
+impl<T, BH: BuildHasher, P: SentinelPolicy> AsRef<T> for SharedCachedHash<T, BH, P> {
+    fn as_ref(&self) -> &T {
+        Self::get(self)
+    }
+}
+
+impl<T, BH: BuildHasher, P: SentinelPolicy> Deref for SharedCachedHash<T, BH, P> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        Self::get(self)
+    }
+}
+
+impl<T, BH: BuildHasher, P: SentinelPolicy> From<CachedHash<T, BH, P>>
+    for SharedCachedHash<T, BH, P>
+{
+    fn from(cached_hash: CachedHash<T, BH, P>) -> Self {
+        Self {
+            inner: Arc::new(cached_hash),
+        }
+    }
+}
+
+impl<T, H: Hasher + Default> From<T> for SharedCachedHash<T, BuildHasherDefault<H>> {
+    fn from(value: T) -> Self {
+        Self::new_with_hasher(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::HashSet;
+    use std::hash::{Hash, Hasher};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::BuildPassThroughHasher;
+
+    fn calculate_hash<T: Hash>(t: &T) -> u64 {
+        let mut s = DefaultHasher::default();
+        t.hash(&mut s);
+        s.finish()
+    }
+
+    /// Counts how many times it was hashed.
+    #[derive(Debug)]
+    struct Counted(u64, Arc<AtomicUsize>);
+
+    impl Clone for Counted {
+        fn clone(&self) -> Self {
+            Self(self.0, Arc::clone(&self.1))
+        }
+    }
+
+    impl Eq for Counted {}
+    impl PartialEq for Counted {
+        fn eq(&self, other: &Self) -> bool {
+            self.0 == other.0
+        }
+    }
+    impl Hash for Counted {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            self.0.hash(state);
+        }
+    }
+
+    #[test]
+    fn clones_share_the_hash() {
+        let hashes = Arc::new(AtomicUsize::new(0));
+        let foo = SharedCachedHash::new(Counted(1, Arc::clone(&hashes)));
+        let clones = vec![foo.clone(); 10];
+        assert_eq!(SharedCachedHash::cached_hash(&clones[0]), None);
+        let hash = calculate_hash(&foo);
+        for clone in &clones {
+            assert!(SharedCachedHash::ptr_eq(clone, &foo));
+            assert!(SharedCachedHash::cached_hash(clone).is_some());
+            assert_eq!(calculate_hash(clone), hash);
+        }
+        #[cfg(not(feature = "verify"))] // `verify` rehashes on every use
+        assert_eq!(hashes.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn hash_matches_cached_hash() {
+        let foo = SharedCachedHash::new("foo".to_string());
+        assert_eq!(
+            calculate_hash(&foo),
+            calculate_hash(&CachedHash::new("foo".to_string()))
+        );
+    }
+
+    #[test]
+    fn make_mut_detaches_and_invalidates() {
+        let mut foo = SharedCachedHash::new("foo".to_string());
+        let bar = foo.clone();
+        let hash = SharedCachedHash::internal_hash(&foo);
+        SharedCachedHash::make_mut(&mut foo).push('d');
+        assert!(!SharedCachedHash::ptr_eq(&foo, &bar));
+        assert_eq!(SharedCachedHash::cached_hash(&foo), None);
+        assert_eq!(SharedCachedHash::cached_hash(&bar), Some(hash));
+        assert_eq!(*foo, "food");
+        assert_eq!(*bar, "foo");
+        assert_ne!(foo, bar);
+    }
+
+    #[test]
+    fn make_mut_unique_does_not_clone() {
+        let mut foo = SharedCachedHash::new("foo".to_string());
+        let before = SharedCachedHash::get(&foo).as_ptr();
+        let _ = SharedCachedHash::internal_hash(&foo);
+        SharedCachedHash::make_mut(&mut foo).make_ascii_uppercase();
+        assert_eq!(SharedCachedHash::get(&foo).as_ptr(), before);
+        assert_eq!(SharedCachedHash::cached_hash(&foo), None);
+    }
+
+    #[test]
+    fn get_mut_only_when_unique() {
+        let mut foo = SharedCachedHash::new(1);
+        let _ = SharedCachedHash::internal_hash(&foo);
+        let bar = foo.clone();
+        assert!(SharedCachedHash::get_mut(&mut foo).is_none());
+        assert!(SharedCachedHash::cached_hash(&foo).is_some());
+        drop(bar);
+        *SharedCachedHash::get_mut(&mut foo).unwrap() = 2;
+        assert_eq!(SharedCachedHash::cached_hash(&foo), None);
+    }
+
+    #[test]
+    #[allow(clippy::redundant_clone)]
+    fn eq_compares_values() {
+        let nan = SharedCachedHash::new(f64::NAN);
+        assert_ne!(nan, nan.clone());
+        let foo = SharedCachedHash::new("foo".to_string());
+        assert!(SharedCachedHash::fast_eq(&foo, &foo.clone()));
+        assert!(SharedCachedHash::fast_eq(
+            &foo,
+            &SharedCachedHash::new("foo".to_string())
+        ));
+        assert!(!SharedCachedHash::fast_eq(
+            &foo,
+            &SharedCachedHash::new("bar".to_string())
+        ));
+    }
+
+    #[test]
+    fn try_unwrap() {
+        let foo = SharedCachedHash::new(1);
+        let bar = foo.clone();
+        let foo = SharedCachedHash::try_unwrap(foo).unwrap_err();
+        drop(bar);
+        assert_eq!(
+            CachedHash::take_value(SharedCachedHash::try_unwrap(foo).unwrap()),
+            1
+        );
+    }
+
+    #[test]
+    #[allow(clippy::mutable_key_type)]
+    fn in_hash_set() {
+        let values = (0..100)
+            .map(|i| SharedCachedHash::new(i.to_string()))
+            .collect::<Vec<_>>();
+        let set: HashSet<_, BuildPassThroughHasher> = values.iter().cloned().collect();
+        assert_eq!(set.len(), 100);
+        assert!(set.contains(&SharedCachedHash::new("42".to_string())));
+        assert!(values
+            .iter()
+            .all(|value| SharedCachedHash::cached_hash(value).is_some()));
+    }
+
+    #[test]
+    fn is_send_and_sync() {
+        const fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SharedCachedHash<String>>();
+    }
+}
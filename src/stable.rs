@@ -0,0 +1,244 @@
+use core::hash::{BuildHasherDefault, Hasher};
+
+use crate::CachedHash;
+
+/// A [`Hasher`] with a documented algorithm that produces the same hashes
+/// across runs, platforms and versions of this crate.
+///
+/// The algorithm is `SipHash-2-4` with the fixed 128-bit key
+/// `00 01 02 … 0f` (the key used by the test vectors of the `SipHash` reference
+/// implementation). Integers are fed into it as little-endian bytes and
+/// `usize`/`isize` are widened to 64 bits, so the result does not depend on the
+/// endianness or pointer width of the platform.
+///
+/// The algorithm, the key and the encoding of integers are part of the public
+/// API and will only change in a new major version of this crate. Note that
+/// the hash of a value also depends on its [`Hash`](core::hash::Hash)
+/// implementation, which this hasher cannot control. Implementations in the
+/// standard library (for example for `str`, which writes the bytes followed by
+/// `0xff`) have not changed in a long time but are not formally guaranteed.
+///
+/// `SipHash` is not the fastest hash function, but with [`CachedHash`](struct@CachedHash) the
+/// internal hash is only computed once per modification.
+#[derive(Debug, Clone)]
+pub struct StableHasher {
+    v0: u64,
+    v1: u64,
+    v2: u64,
+    v3: u64,
+    tail: u64,
+    ntail: usize,
+    length: usize,
+}
+
+/// A [`BuildHasher`](core::hash::BuildHasher) creating [`StableHasher`]s.
+pub type BuildStableHasher = BuildHasherDefault<StableHasher>;
+
+/// A [`CachedHash`](struct@CachedHash) whose internal hash is computed by [`StableHasher`].
+///
+/// For the same input written into the hasher by `T`'s
+/// [`Hash`](core::hash::Hash) implementation, [`CachedHash::internal_hash`] is
+/// identical across runs, platforms and versions of this crate within a major
+/// release. This makes it suitable for persisting the internal hash (see
+/// `serde_with_hash`) or comparing it between processes.
+///
+/// Create values using [`CachedHash::new_with_hasher`] or [`From`]:
+///
+/// ```
+/// use cachedhash::{CachedHash, StableCachedHash};
+///
+/// let foo: StableCachedHash<u64> = CachedHash::new_with_hasher(42);
+/// assert_eq!(CachedHash::internal_hash(&foo).get(), 0x2cbe_815a_255f_af48);
+/// ```
+pub type StableCachedHash<T> = CachedHash<T, BuildStableHasher>;
+
+const KEY0: u64 = 0x0706_0504_0302_0100;
+const KEY1: u64 = 0x0f0e_0d0c_0b0a_0908;
+
+impl StableHasher {
+    const fn with_keys(key0: u64, key1: u64) -> Self {
+        Self {
+            v0: key0 ^ 0x736f_6d65_7073_6575,
+            v1: key1 ^ 0x646f_7261_6e64_6f6d,
+            v2: key0 ^ 0x6c79_6765_6e65_7261,
+            v3: key1 ^ 0x7465_6462_7974_6573,
+            tail: 0,
+            ntail: 0,
+            length: 0,
+        }
+    }
+
+    #[inline]
+    const fn round(&mut self) {
+        self.v0 = self.v0.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(13);
+        self.v1 ^= self.v0;
+        self.v0 = self.v0.rotate_left(32);
+        self.v2 = self.v2.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(16);
+        self.v3 ^= self.v2;
+        self.v0 = self.v0.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(21);
+        self.v3 ^= self.v0;
+        self.v2 = self.v2.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(17);
+        self.v1 ^= self.v2;
+        self.v2 = self.v2.rotate_left(32);
+    }
+
+    #[inline]
+    const fn compress(&mut self, word: u64) {
+        self.v3 ^= word;
+        self.round();
+        self.round();
+        self.v0 ^= word;
+    }
+}
+
+/// Loads up to 8 bytes as a little-endian integer.
+#[inline]
+fn load_le(bytes: &[u8]) -> u64 {
+    let mut word = [0; 8];
+    word[..bytes.len()].copy_from_slice(bytes);
+    u64::from_le_bytes(word)
+}
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self::with_keys(KEY0, KEY1)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.length = self.length.wrapping_add(bytes.len());
+        let mut rest = bytes;
+        if self.ntail != 0 {
+            let needed = 8 - self.ntail;
+            let (head, tail) = rest.split_at(needed.min(rest.len()));
+            self.tail |= load_le(head) << (8 * self.ntail);
+            if head.len() < needed {
+                self.ntail += head.len();
+                return;
+            }
+            self.compress(self.tail);
+            rest = tail;
+        }
+        let mut chunks = rest.chunks_exact(8);
+        for chunk in &mut chunks {
+            self.compress(load_le(chunk));
+        }
+        let remainder = chunks.remainder();
+        self.tail = load_le(remainder);
+        self.ntail = remainder.len();
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_i64(i as i64);
+    }
+
+    fn finish(&self) -> u64 {
+        let mut state = self.clone();
+        #[allow(clippy::cast_possible_truncation)]
+        let length = self.length as u8;
+        let last = (u64::from(length) << 56) | self.tail;
+        state.compress(last);
+        state.v2 ^= 0xff;
+        state.round();
+        state.round();
+        state.round();
+        state.round();
+        state.v0 ^ state.v1 ^ state.v2 ^ state.v3
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::hash::{BuildHasher, Hash, Hasher};
+
+    use super::*;
+
+    fn sip(bytes: &[u8]) -> u64 {
+        let mut hasher = StableHasher::default();
+        hasher.write(bytes);
+        hasher.finish()
+    }
+
+    fn internal_hash<T: Hash>(value: T) -> u64 {
+        CachedHash::internal_hash(&StableCachedHash::new_with_hasher(value)).get()
+    }
+
+    #[test]
+    fn reference_vectors() {
+        let message = (0..64).collect::<std::vec::Vec<u8>>();
+        assert_eq!(sip(&message[..0]), 0x726f_db47_dd0e_0e31);
+        assert_eq!(sip(&message[..1]), 0x74f8_39c5_93dc_67fd);
+        assert_eq!(sip(&message[..2]), 0x0d6c_8009_d9a9_4f5a);
+        assert_eq!(sip(&message[..3]), 0x8567_6696_d7fb_7e2d);
+        assert_eq!(sip(&message[..63]), 0x958a_324c_eb06_4572);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn matches_std_sip_hasher() {
+        let message = (0..=255).collect::<std::vec::Vec<u8>>();
+        for length in 0..message.len() {
+            let mut std_hasher = core::hash::SipHasher::new_with_keys(KEY0, KEY1);
+            std_hasher.write(&message[..length]);
+            assert_eq!(sip(&message[..length]), std_hasher.finish());
+        }
+    }
+
+    #[test]
+    fn split_writes() {
+        let message = (0..64).collect::<std::vec::Vec<u8>>();
+        for split in 0..message.len() {
+            for second in split..message.len() {
+                let mut hasher = StableHasher::default();
+                hasher.write(&message[..split]);
+                hasher.write(&message[split..second]);
+                hasher.write(&message[second..]);
+                assert_eq!(hasher.finish(), sip(&message), "{split} {second}");
+            }
+        }
+    }
+
+    #[test]
+    fn integers_are_little_endian() {
+        let build = BuildStableHasher::default();
+        assert_eq!(build.hash_one(0x0201_u16), sip(&[1, 2]));
+        assert_eq!(build.hash_one(0x0403_0201_u32), sip(&[1, 2, 3, 4]));
+        assert_eq!(build.hash_one(1_usize), sip(&1_u64.to_le_bytes()));
+        assert_eq!(build.hash_one(-1_isize), sip(&[0xff; 8]));
+    }
+
+    #[test]
+    fn golden_values() {
+        assert_eq!(internal_hash(42_u64), 0x2cbe_815a_255f_af48);
+        assert_eq!(internal_hash("hello"), 0x2e14_7663_d70a_a685);
+        assert_eq!(internal_hash([1_u8, 2, 3]), 0x6a99_e45f_1ebf_3137);
+        assert_eq!(internal_hash((1_u32, -1_i64)), 0x6c49_1362_47f2_9706);
+        assert_eq!(internal_hash(-2_isize), internal_hash(-2_i64));
+        assert_eq!(internal_hash(-2_isize), 0x9a4a_1224_ffeb_16b4);
+    }
+}
@@ -0,0 +1,403 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ptr;
+use std::sync::atomic::Ordering;
+use std::sync::RwLock;
+
+#[cfg(not(feature = "portable-atomic"))]
+use std::sync::atomic::AtomicU64;
+
+#[cfg(feature = "portable-atomic")]
+use portable_atomic::AtomicU64;
+
+/// Counts of the events recorded by the `instrument` feature.
+///
+/// See [`stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Counts {
+    /// How many times a cached hash was used instead of hashing the value.
+    pub hits: u64,
+    /// How many times the hash had to be computed because none was cached.
+    pub misses: u64,
+    /// How many times the hash was invalidated by mutable access to the value,
+    /// for example through [`CachedHash::get_mut`](crate::CachedHash::get_mut),
+    /// [`DerefMut`](std::ops::DerefMut), [`AsMut`] or
+    /// [`BorrowMut`](std::borrow::BorrowMut).
+    pub mutable_invalidations: u64,
+    /// How many times the hash was invalidated by an explicit call to
+    /// [`CachedHash::invalidate_hash`](crate::CachedHash::invalidate_hash).
+    pub explicit_invalidations: u64,
+}
+
+impl Counts {
+    /// Returns the fraction of hash uses served from the cache, or [`None`]
+    /// if no hash was used at all.
+    #[must_use]
+    pub fn hit_rate(&self) -> Option<f64> {
+        let uses = self.hits + self.misses;
+        #[allow(clippy::cast_precision_loss)] // a rate does not need all the bits
+        (uses != 0).then(|| self.hits as f64 / uses as f64)
+    }
+
+    const fn is_zero(&self) -> bool {
+        self.hits == 0
+            && self.misses == 0
+            && self.mutable_invalidations == 0
+            && self.explicit_invalidations == 0
+    }
+}
+
+/// A snapshot of the statistics recorded by the `instrument` feature.
+///
+/// See [`stats`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Stats {
+    /// The counts over all [`BuildHasher`](std::hash::BuildHasher) types.
+    pub total: Counts,
+    /// The counts for each [`BuildHasher`](std::hash::BuildHasher) type, keyed
+    /// by [`type_name`](std::any::type_name). Types without any events since
+    /// the last [`reset_stats`] are left out.
+    pub per_build_hasher: BTreeMap<&'static str, Counts>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    mutable_invalidations: AtomicU64,
+    explicit_invalidations: AtomicU64,
+}
+
+impl Counters {
+    const fn new() -> Self {
+        Self {
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            mutable_invalidations: AtomicU64::new(0),
+            explicit_invalidations: AtomicU64::new(0),
+        }
+    }
+
+    fn load(&self) -> Counts {
+        Counts {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            mutable_invalidations: self.mutable_invalidations.load(Ordering::Relaxed),
+            explicit_invalidations: self.explicit_invalidations.load(Ordering::Relaxed),
+        }
+    }
+
+    fn reset(&self) {
+        self.hits.store(0, Ordering::Relaxed);
+        self.misses.store(0, Ordering::Relaxed);
+        self.mutable_invalidations.store(0, Ordering::Relaxed);
+        self.explicit_invalidations.store(0, Ordering::Relaxed);
+    }
+}
+
+static TOTAL: Counters = Counters::new();
+
+/// The counters of each `BuildHasher` type keyed by its
+/// [`type_name`](std::any::type_name). They are leaked so that they can be
+/// updated without holding the lock, there is one per type name that was used.
+///
+/// Types with the same name (which [`type_name`](std::any::type_name) does not
+/// rule out) share their counters, the same way they would share an entry of
+/// [`Stats::per_build_hasher`].
+static PER_BUILD_HASHER: RwLock<BTreeMap<&'static str, &'static Counters>> =
+    RwLock::new(BTreeMap::new());
+
+thread_local! {
+    /// The counters already resolved by this thread, keyed by the address of
+    /// the type name so that recording an event needs neither the lock nor
+    /// comparing names. There are only as many entries as `BuildHasher` types
+    /// used by the thread.
+    static RESOLVED: RefCell<Vec<(&'static str, &'static Counters)>> =
+        const { RefCell::new(Vec::new()) };
+}
+
+fn counters<BH>() -> &'static Counters {
+    let name = std::any::type_name::<BH>();
+    RESOLVED
+        .try_with(|resolved| {
+            let mut resolved = resolved.borrow_mut();
+            if let Some(&(_, counters)) = resolved.iter().find(|(key, _)| ptr::eq(*key, name)) {
+                return counters;
+            }
+            let counters = register(name);
+            resolved.push((name, counters));
+            counters
+        })
+        // the thread local is already destroyed when called from a destructor
+        // at thread exit
+        .unwrap_or_else(|_| register(name))
+}
+
+/// Returns the counters of the `BuildHasher` type named `name`, creating them
+/// if this is the first use of the type.
+#[cold]
+fn register(name: &'static str) -> &'static Counters {
+    let counters = PER_BUILD_HASHER
+        .read()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .get(name)
+        .copied();
+    counters.unwrap_or_else(|| {
+        PER_BUILD_HASHER
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .entry(name)
+            .or_insert_with(|| Box::leak(Box::default()))
+    })
+}
+
+#[inline]
+fn record<BH>(counter: fn(&Counters) -> &AtomicU64) {
+    counter(&TOTAL).fetch_add(1, Ordering::Relaxed);
+    counter(counters::<BH>()).fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records that a cached hash was used.
+#[inline]
+pub fn hit<BH>() {
+    record::<BH>(|counters| &counters.hits);
+}
+
+/// Records that a hash was computed.
+#[inline]
+pub fn miss<BH>() {
+    record::<BH>(|counters| &counters.misses);
+}
+
+/// Records that the hash was invalidated by mutable access.
+#[inline]
+pub fn mutable_invalidation<BH>() {
+    record::<BH>(|counters| &counters.mutable_invalidations);
+}
+
+/// Records that the hash was invalidated explicitly.
+#[inline]
+pub fn explicit_invalidation<BH>() {
+    record::<BH>(|counters| &counters.explicit_invalidations);
+}
+
+/// Returns a snapshot of the cache statistics since the start of the program
+/// or the last [`reset_stats`].
+///
+/// All the cached hash wrappers, such as [`CachedHash`](struct@crate::CachedHash)
+/// and [`LocalCachedHash`](crate::LocalCachedHash), record their events.
+///
+/// The counters are updated independently of each other with relaxed atomics
+/// so a snapshot taken while other threads are hashing is not necessarily
+/// consistent. Only available with the `instrument` feature.
+#[must_use]
+pub fn stats() -> Stats {
+    let per_build_hasher = PER_BUILD_HASHER
+        .read()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .iter()
+        .map(|(&name, counters)| (name, counters.load()))
+        .filter(|(_, counts)| !counts.is_zero())
+        .collect();
+    Stats {
+        total: TOTAL.load(),
+        per_build_hasher,
+    }
+}
+
+/// Resets all the statistics returned by [`stats`] to zero.
+///
+/// Only available with the `instrument` feature.
+pub fn reset_stats() {
+    TOTAL.reset();
+    for counters in PER_BUILD_HASHER
+        .read()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .values()
+    {
+        counters.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{BuildHasherDefault, Hash, Hasher};
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{CachedHash, CachedHash128, CachedHash32, DoubleHasher, LocalCachedHash};
+
+    /// The statistics are global so tests resetting them must not run concurrently.
+    static STATS_LOCK: Mutex<()> = Mutex::new(());
+
+    /// A hasher type used only by these tests so that its counts are not
+    /// affected by other tests running concurrently.
+    #[derive(Default)]
+    struct TestHasher(DefaultHasher);
+
+    impl Hasher for TestHasher {
+        fn write(&mut self, bytes: &[u8]) {
+            self.0.write(bytes);
+        }
+
+        fn finish(&self) -> u64 {
+            self.0.finish()
+        }
+    }
+
+    type TestBuild = BuildHasherDefault<TestHasher>;
+    type TestBuild128 = BuildHasherDefault<DoubleHasher<TestHasher>>;
+
+    fn counts() -> Counts {
+        counts_of::<TestBuild>()
+    }
+
+    fn counts_of<BH>() -> Counts {
+        stats()
+            .per_build_hasher
+            .get(std::any::type_name::<BH>())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn calculate_hash<T: Hash>(t: &T) -> u64 {
+        let mut s = DefaultHasher::default();
+        t.hash(&mut s);
+        s.finish()
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)] // 2 / 5 rounds the same way as 0.4
+    fn counts_events() {
+        let _lock = STATS_LOCK
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        reset_stats();
+        let mut foo: CachedHash<String, TestBuild> = CachedHash::new_with_hasher("foo".into());
+        calculate_hash(&foo);
+        calculate_hash(&foo);
+        let _ = CachedHash::internal_hash(&foo);
+        foo.push('d');
+        calculate_hash(&foo);
+        CachedHash::invalidate_hash(&mut foo);
+        CachedHash::update(&mut foo, String::clear);
+        let _ = CachedHash::cached_hash(&foo);
+
+        let counts = counts();
+        assert_eq!(counts.hits, 2);
+        assert_eq!(counts.misses, 3);
+        assert_eq!(counts.mutable_invalidations, 2);
+        assert_eq!(counts.explicit_invalidations, 1);
+        assert_eq!(counts.hit_rate(), Some(0.4));
+        let total = stats().total;
+        assert!(total.hits >= counts.hits && total.misses >= counts.misses);
+    }
+
+    #[test]
+    fn counts_local_events() {
+        let _lock = STATS_LOCK
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        reset_stats();
+        let mut foo: LocalCachedHash<u64, TestBuild> = LocalCachedHash::new_hashed_with_hasher(1);
+        calculate_hash(&foo);
+        *foo.as_mut() = 2;
+        LocalCachedHash::invalidate_hash(&mut foo);
+
+        assert_eq!(
+            counts(),
+            Counts {
+                hits: 1,
+                misses: 1,
+                mutable_invalidations: 1,
+                explicit_invalidations: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn counts_32_bit_events() {
+        let _lock = STATS_LOCK
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        reset_stats();
+        let mut foo: CachedHash32<u64, TestBuild> = CachedHash32::new_hashed_with_hasher(1);
+        calculate_hash(&foo);
+        *foo.as_mut() = 2;
+        CachedHash32::invalidate_hash(&mut foo);
+
+        assert_eq!(
+            counts(),
+            Counts {
+                hits: 1,
+                misses: 1,
+                mutable_invalidations: 1,
+                explicit_invalidations: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn counts_128_bit_events() {
+        let _lock = STATS_LOCK
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        reset_stats();
+        let mut foo: CachedHash128<u64, TestBuild128> = CachedHash128::new_with_hasher(1);
+        calculate_hash(&foo);
+        calculate_hash(&foo);
+        let _ = CachedHash128::fingerprint(&foo);
+        *foo.as_mut() = 2;
+        calculate_hash(&foo);
+        CachedHash128::invalidate_hash(&mut foo);
+
+        assert_eq!(
+            counts_of::<TestBuild128>(),
+            Counts {
+                hits: 2,
+                misses: 2,
+                mutable_invalidations: 1,
+                explicit_invalidations: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn counts_from_all_threads() {
+        let _lock = STATS_LOCK
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        reset_stats();
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    let foo: CachedHash<u64, TestBuild> = CachedHash::new_with_hasher(1);
+                    for _ in 0..10 {
+                        calculate_hash(&foo);
+                    }
+                });
+            }
+        });
+        let counts = counts();
+        assert_eq!(counts.misses, 4);
+        assert_eq!(counts.hits, 36);
+    }
+
+    #[test]
+    fn reset() {
+        let _lock = STATS_LOCK
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        calculate_hash(&CachedHash::<u8, TestBuild>::new_with_hasher(1));
+        assert_ne!(counts(), Counts::default());
+        reset_stats();
+        assert_eq!(counts(), Counts::default());
+        assert!(!stats()
+            .per_build_hasher
+            .contains_key(std::any::type_name::<TestBuild>()));
+        assert_eq!(Counts::default().hit_rate(), None);
+    }
+}
@@ -0,0 +1,225 @@
+use std::fmt::Display;
+use std::sync::{Arc, RwLock};
+
+/// Information about a stale cached hash detected by the `verify` feature.
+///
+/// With the `verify` feature enabled, every time a cached hash is fed into a
+/// [`Hasher`](std::hash::Hasher) or returned by `internal_hash` (for example
+/// [`CachedHash::internal_hash`](crate::CachedHash::internal_hash)) the internal
+/// hash is recomputed and compared with the cached one. The cached hashes
+/// compared by the [`PartialEq`] shortcut are not checked. A mismatch means that the stored value was
+/// modified without invalidating the hash, typically through interior mutability.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StaleHash {
+    /// The name of the type of the stored value.
+    pub type_name: &'static str,
+    /// The internal hash that was cached.
+    pub cached: u64,
+    /// The internal hash of the current value.
+    pub actual: u64,
+}
+
+impl Display for StaleHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "stale cached hash for {}: cached {:#018x}, actual {:#018x}",
+            self.type_name, self.cached, self.actual
+        )
+    }
+}
+
+type BoxedHook = Box<dyn Fn(&StaleHash) + Send + Sync + 'static>;
+type Hook = Arc<dyn Fn(&StaleHash) + Send + Sync + 'static>;
+
+static HOOK: RwLock<Option<Hook>> = RwLock::new(None);
+
+/// Registers a custom hook called whenever a stale cached hash is detected,
+/// replacing any previously registered hook.
+///
+/// By default a stale hash causes a panic with a message describing the
+/// [`StaleHash`]. Only available with the `verify` feature.
+pub fn set_stale_hash_hook(hook: BoxedHook) {
+    *HOOK
+        .write()
+        .unwrap_or_else(std::sync::PoisonError::into_inner) = Some(Arc::from(hook));
+}
+
+/// Unregisters the current stale hash hook, restoring the default one which panics.
+/// Returns the unregistered hook if any.
+///
+/// Only available with the `verify` feature.
+pub fn take_stale_hash_hook() -> Option<BoxedHook> {
+    HOOK.write()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .take()
+        .map(|hook| Box::new(move |stale: &StaleHash| hook(stale)) as BoxedHook)
+}
+
+/// Compares the cached internal hash of a value of type `T` with its actual
+/// internal hash and reports a mismatch to the registered hook.
+#[inline]
+pub fn check_cached_hash<T: ?Sized>(cached: u64, actual: u64) {
+    if cached != actual {
+        report(StaleHash {
+            type_name: std::any::type_name::<T>(),
+            cached,
+            actual,
+        });
+    }
+}
+
+#[cold]
+#[inline(never)]
+fn report(stale: StaleHash) {
+    let hook = HOOK
+        .read()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .clone();
+    match hook {
+        Some(hook) => hook(&stale),
+        None => panic!("{stale}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{CachedHash, CachedHash128, HashCache, LocalCachedHash};
+
+    /// The hook is global so tests touching it must not run concurrently.
+    static HOOK_LOCK: Mutex<()> = Mutex::new(());
+
+    #[derive(PartialEq, Eq)]
+    struct Sneaky(Cell<u64>);
+
+    impl Hash for Sneaky {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.0.get().hash(state);
+        }
+    }
+
+    fn calculate_hash<T: Hash>(t: &T) -> u64 {
+        let mut s = DefaultHasher::default();
+        t.hash(&mut s);
+        s.finish()
+    }
+
+    #[test]
+    fn no_report_when_fresh() {
+        let _lock = HOOK_LOCK
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let mut foo = CachedHash::new(Sneaky(Cell::new(1)));
+        calculate_hash(&foo);
+        calculate_hash(&foo);
+        foo.0.set(2);
+        CachedHash::invalidate_hash(&mut foo);
+        calculate_hash(&foo);
+    }
+
+    #[test]
+    #[should_panic = "stale cached hash for cachedhash::verify::tests::Sneaky"]
+    fn panics_on_stale_hash() {
+        let _lock = HOOK_LOCK
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let foo = CachedHash::new(Sneaky(Cell::new(1)));
+        calculate_hash(&foo);
+        foo.0.set(2);
+        calculate_hash(&foo);
+    }
+
+    #[test]
+    #[should_panic = "stale cached hash for cachedhash::verify::tests::Sneaky"]
+    fn panics_on_stale_local_hash() {
+        let _lock = HOOK_LOCK
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let foo = LocalCachedHash::new(Sneaky(Cell::new(1)));
+        calculate_hash(&foo);
+        foo.0.set(2);
+        calculate_hash(&foo);
+    }
+
+    #[test]
+    #[should_panic = "stale cached hash for cachedhash::verify::tests::Sneaky"]
+    fn panics_on_stale_internal_hash() {
+        let _lock = HOOK_LOCK
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let foo = CachedHash::new(Sneaky(Cell::new(1)));
+        let _ = CachedHash::internal_hash(&foo);
+        foo.0.set(2);
+        let _ = CachedHash::internal_hash(&foo);
+    }
+
+    #[test]
+    #[should_panic = "stale cached hash for cachedhash::verify::tests::Sneaky"]
+    fn panics_on_stale_fingerprint() {
+        let _lock = HOOK_LOCK
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let foo = CachedHash128::new(Sneaky(Cell::new(1)));
+        calculate_hash(&foo);
+        foo.0.set(2);
+        calculate_hash(&foo);
+    }
+
+    #[test]
+    fn no_report_when_fingerprint_fresh() {
+        let _lock = HOOK_LOCK
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let mut foo = CachedHash128::new(Sneaky(Cell::new(1)));
+        let _ = CachedHash128::fingerprint(&foo);
+        let _ = CachedHash128::fingerprint(&foo);
+        foo.0.set(2);
+        CachedHash128::invalidate_hash(&mut foo);
+        let _ = CachedHash128::fingerprint(&foo);
+        let _ = CachedHash128::fingerprint(&foo);
+    }
+
+    #[test]
+    #[should_panic = "stale cached hash for cachedhash::verify::tests::Sneaky"]
+    fn panics_on_stale_hash_cache_internal_hash() {
+        let _lock = HOOK_LOCK
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let cache = HashCache::new();
+        let field = Sneaky(Cell::new(1));
+        let _ = cache.internal_hash(&field);
+        field.0.set(2);
+        let _ = cache.internal_hash(&field);
+    }
+
+    #[test]
+    fn hook_gets_called() {
+        static REPORTED: Mutex<Vec<StaleHash>> = Mutex::new(Vec::new());
+
+        let _lock = HOOK_LOCK
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        set_stale_hash_hook(Box::new(|stale| REPORTED.lock().unwrap().push(*stale)));
+        let foo = CachedHash::new(Sneaky(Cell::new(1)));
+        let cached = CachedHash::internal_hash(&foo);
+        foo.0.set(2);
+        calculate_hash(&foo);
+        assert!(take_stale_hash_hook().is_some());
+        assert!(take_stale_hash_hook().is_none());
+
+        let reported = std::mem::take(&mut *REPORTED.lock().unwrap());
+        assert_eq!(reported.len(), 1);
+        assert_eq!(reported[0].type_name, std::any::type_name::<Sneaky>());
+        assert_eq!(reported[0].cached, cached.get());
+        assert_eq!(
+            reported[0].actual,
+            CachedHash::internal_hash(&CachedHash::new(Sneaky(Cell::new(2)))).get()
+        );
+    }
+}
@@ -0,0 +1,198 @@
+//! Tests of `#[derive(CachedHash)]`. They live here rather than next to
+//! `HashCache` because the generated code refers to the crate as `::cachedhash`.
+#![cfg(feature = "std")]
+
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
+
+use cachedhash::{BuildPassThroughHasher, HashCache, NoSentinel};
+use cachedhash_derive::CachedHash;
+
+fn calculate_hash<T: Hash>(t: &T) -> u64 {
+    let mut s = DefaultHasher::default();
+    t.hash(&mut s);
+    s.finish()
+}
+
+#[derive(CachedHash, Debug, Clone, PartialEq, Eq)]
+struct Document {
+    #[hash]
+    title: String,
+    body: Vec<String>,
+    #[hash(skip)]
+    views: u64,
+    cache: HashCache,
+}
+
+impl Document {
+    fn new(title: &str, body: &[&str]) -> Self {
+        Self {
+            title: title.to_string(),
+            body: body.iter().map(ToString::to_string).collect(),
+            views: 0,
+            cache: HashCache::new(),
+        }
+    }
+}
+
+#[test]
+fn matches_cached_hash_of_hashed_fields() {
+    let document = Document::new("foo", &["bar", "baz"]);
+    let expected = cachedhash::CachedHash::new(("foo".to_string(), vec!["bar", "baz"]));
+    assert_eq!(calculate_hash(&document), calculate_hash(&expected));
+    assert_eq!(
+        document.cache.cached_hash(),
+        cachedhash::CachedHash::cached_hash(&expected)
+    );
+}
+
+#[test]
+fn single_field_matches_cached_hash() {
+    #[derive(CachedHash)]
+    struct Name {
+        name: String,
+        cache: HashCache<BuildHasherDefault<DefaultHasher>, NoSentinel>,
+    }
+
+    let name = Name {
+        name: "foo".to_string(),
+        cache: HashCache::default(),
+    };
+    let expected = cachedhash::CachedHash::new_with_sentinel(
+        "foo".to_string(),
+        BuildHasherDefault::<DefaultHasher>::default(),
+        NoSentinel,
+    );
+    assert_eq!(calculate_hash(&name), calculate_hash(&expected));
+}
+
+#[test]
+fn skipped_fields_keep_the_hash() {
+    let mut document = Document::new("foo", &["bar"]);
+    let hash = calculate_hash(&document);
+    let cached = document.cache.cached_hash();
+    assert!(cached.is_some());
+    document.set_views(10);
+    *document.views_mut() += 1;
+    assert_eq!(*document.views(), 11);
+    assert_eq!(document.cache.cached_hash(), cached);
+    assert_eq!(calculate_hash(&document), hash);
+}
+
+#[test]
+fn hashed_fields_invalidate() {
+    let mut document = Document::new("foo", &["bar"]);
+    let hash = calculate_hash(&document);
+    document.set_title("food".to_string());
+    assert_eq!(document.cache.cached_hash(), None);
+    assert_ne!(calculate_hash(&document), hash);
+
+    let hash = calculate_hash(&document);
+    document.body_mut().push("baz".to_string());
+    assert_eq!(document.cache.cached_hash(), None);
+    assert_ne!(calculate_hash(&document), hash);
+    assert_eq!(document.body(), &["bar", "baz"]);
+    assert_eq!(document.title(), "food");
+}
+
+#[test]
+fn explicit_invalidation() {
+    struct Counter(Cell<u64>);
+
+    impl Hash for Counter {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.0.get().hash(state);
+        }
+    }
+
+    #[derive(CachedHash)]
+    struct Interior {
+        value: Counter,
+        cache: HashCache,
+    }
+
+    let mut interior = Interior {
+        value: Counter(Cell::new(1)),
+        cache: HashCache::new(),
+    };
+    let hash = calculate_hash(&interior);
+    interior.value().0.set(2);
+    #[cfg(not(feature = "verify"))] // `verify` panics on the stale hash
+    assert_eq!(calculate_hash(&interior), hash);
+    interior.cache.invalidate_hash();
+    assert_ne!(calculate_hash(&interior), hash);
+}
+
+#[test]
+fn generics() {
+    #[derive(CachedHash)]
+    struct Pair<A, B, BH: BuildHasher> {
+        first: A,
+        #[hash(skip)]
+        second: B,
+        cache: HashCache<BH>,
+    }
+
+    let mut pair = Pair {
+        first: 1_u64,
+        second: vec![0.5_f64],
+        cache: HashCache::new_with_build_hasher(BuildPassThroughHasher::default()),
+    };
+    assert_eq!(pair.cache.internal_hash(&(&1_u64,)).get(), 1);
+    pair.second_mut().push(1.5);
+    assert!(pair.cache.cached_hash().is_some());
+    assert_eq!(calculate_hash(&pair), calculate_hash(&1_u64));
+}
+
+#[test]
+fn no_getter_allows_own_method() {
+    #[derive(CachedHash)]
+    struct Buffer {
+        #[hash(no_getter)]
+        len: usize,
+        cache: HashCache,
+    }
+
+    impl Buffer {
+        const fn len(&self) -> usize {
+            self.len
+        }
+    }
+
+    let mut buffer = Buffer {
+        len: 1,
+        cache: HashCache::new(),
+    };
+    let hash = calculate_hash(&buffer);
+    buffer.set_len(2);
+    assert_eq!(buffer.len(), 2);
+    assert_eq!(buffer.cache.cached_hash(), None);
+    assert_ne!(calculate_hash(&buffer), hash);
+}
+
+#[test]
+fn comparisons_ignore_the_cache() {
+    let foo = Document::new("foo", &[]);
+    let bar = foo.clone();
+    calculate_hash(&foo);
+    assert!(foo.cache.cached_hash().is_some());
+    assert_eq!(foo, bar);
+    assert_eq!(
+        HashCache::<BuildPassThroughHasher>::default().cmp(&HashCache::default()),
+        Ordering::Equal
+    );
+}
+
+#[test]
+#[allow(clippy::mutable_key_type)]
+fn in_hash_set() {
+    let mut set = HashSet::<_, BuildPassThroughHasher>::default();
+    for i in 0..100 {
+        set.insert(Document::new(&i.to_string(), &["body"]));
+    }
+    assert!(!set.insert(Document::new("42", &["body"])));
+    assert_eq!(set.len(), 100);
+}